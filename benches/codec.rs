@@ -0,0 +1,84 @@
+//! Compares the codec against the per-pixel copies it used to make.
+//!
+//! Run with `cargo bench`.
+
+use bytes::{BufMut, BytesMut};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use tokio_util::codec::{Decoder, Encoder};
+
+use opc::{Command, Message, OpcCodec};
+
+const PIXELS: usize = 20_000;
+
+fn frame() -> Message {
+    let pixels: Vec<[u8; 3]> = (0..PIXELS).map(|i| [i as u8, (i >> 8) as u8, 0x55]).collect();
+    Message::from_pixels(1, &pixels)
+}
+
+/// Decoding as it was done with `Vec<[u8; 3]>` pixels
+fn decode_per_pixel(src: &mut BytesMut) -> Vec<[u8; 3]> {
+    let length = u16::from_be_bytes([src[2], src[3]]) as usize;
+    let frame = src.split_to(4 + length);
+    frame[4..4 + length - (length % 3)]
+        .chunks(3)
+        .map(|chunk| [chunk[0], chunk[1], chunk[2]])
+        .collect()
+}
+
+/// Encoding as it was done with `Vec<[u8; 3]>` pixels
+fn encode_per_pixel(channel: u8, pixels: &[[u8; 3]], dst: &mut BytesMut) {
+    dst.reserve(4 + pixels.len() * 3);
+    dst.put_slice(&[channel, 0]);
+    dst.put_u16(pixels.len() as u16 * 3);
+    for pixel in pixels {
+        dst.put_slice(pixel);
+    }
+}
+
+fn decode(c: &mut Criterion) {
+    let mut encoded = BytesMut::new();
+    OpcCodec::new().encode(frame(), &mut encoded).unwrap();
+
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(PIXELS as u64));
+    group.bench_function("per_pixel_copy", |b| {
+        b.iter_batched(|| encoded.clone(), |mut src| decode_per_pixel(&mut src), BatchSize::SmallInput)
+    });
+    group.bench_function("opc_codec", |b| {
+        let mut codec = OpcCodec::new();
+        b.iter_batched(|| encoded.clone(),
+                       |mut src| codec.decode(&mut src).unwrap(),
+                       BatchSize::SmallInput)
+    });
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let msg = frame();
+    let pixels = match msg.command {
+        Command::SetPixelColors { ref pixels } => pixels.to_vec(),
+        _ => unreachable!(),
+    };
+
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(PIXELS as u64));
+    group.bench_function("per_pixel_copy", |b| {
+        let mut dst = BytesMut::new();
+        b.iter(|| {
+            dst.clear();
+            encode_per_pixel(1, &pixels, &mut dst);
+        })
+    });
+    group.bench_function("opc_codec", |b| {
+        let mut codec = OpcCodec::new();
+        let mut dst = BytesMut::new();
+        b.iter(|| {
+            dst.clear();
+            codec.encode(msg.clone(), &mut dst).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode, encode);
+criterion_main!(benches);
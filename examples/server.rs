@@ -1,38 +1,21 @@
-extern crate opc;
-extern crate futures;
-extern crate tokio_core;
-extern crate tokio_io;
+use opc::server::{Handler, Server};
 
-use opc::OpcCodec;
-use futures::{Future, Stream};
+struct Print;
 
-use tokio_io::AsyncRead;
-use tokio_core::net::TcpListener;
-use tokio_core::reactor::Core;
+impl Handler for Print {
+    fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) {
+        println!("GOT: {} pixels on channel {}", pixels.len(), channel);
+    }
 
-fn main() {
-    let mut core = Core::new().unwrap();
-    let handle = core.handle();
-    let remote_addr = "127.0.0.1:7890".parse().unwrap();
-
-    let listener = TcpListener::bind(&remote_addr, &handle).unwrap();
-
-    // Accept all incoming sockets
-    let server = listener.incoming().for_each(move |(socket, _)| {
-        // `OpcCodec` handles encoding / decoding frames.
-        let transport = socket.framed(OpcCodec);
-
-        let process_connection = transport.for_each(|message| {
-            println!("GOT: {:?}", message);
-            Ok(())
-        });
-
-        // Spawn a new task dedicated to processing the connection
-        handle.spawn(process_connection.map_err(|_| ()));
+    fn on_sysex(&mut self, channel: u8, id: [u8; 2], data: &[u8]) {
+        println!("GOT: system exclusive {:?} on channel {}: {:?}", id, channel, data);
+    }
+}
 
-        Ok(())
-    });
+#[tokio::main]
+async fn main() {
+    // Channel 0 broadcasts are delivered to each of these channels
+    let server = Server::new(Print).channels(1..=8);
 
-    // Open listener
-    core.run(server).unwrap();
+    server.listen("127.0.0.1:7890").await.unwrap();
 }
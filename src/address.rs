@@ -0,0 +1,131 @@
+use std::convert::{Infallible, TryFrom};
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Where an OPC server listens, written as `tcp://host:port` or `unix:///path/to/socket`.
+///
+/// Strings without a scheme are TCP addresses, so plain `host:port` strings keep working.
+/// Any other scheme is an error, rather than a host name that never resolves:
+///
+/// ```rust
+/// use opc::Address;
+///
+/// assert_eq!("unix:///run/opc.sock".parse(), Ok(Address::Unix("/run/opc.sock".into())));
+/// assert_eq!("tcp://127.0.0.1:7890".parse(), Ok(Address::Tcp("127.0.0.1:7890".to_string())));
+/// assert_eq!("127.0.0.1:7890".parse(), Ok(Address::Tcp("127.0.0.1:7890".to_string())));
+/// assert!("udp://127.0.0.1:7890".parse::<Address>().is_err());
+/// ```
+#[derive (Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Address {
+    /// A `host:port` pair, resolved when connecting.
+    Tcp(String),
+    /// The path of a Unix domain socket, only supported on Unix.
+    Unix(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = ParseAddressError;
+
+    fn from_str(addr: &str) -> Result<Address, ParseAddressError> {
+        match addr.split_once("://") {
+            Some(("unix", path)) => Ok(Address::Unix(path.into())),
+            Some(("tcp", addr)) => Ok(Address::Tcp(addr.to_string())),
+            Some((scheme, _)) => Err(ParseAddressError { scheme: scheme.to_string() }),
+            // A port never starts with a slash, so this is a scheme missing its slashes, like `unix:/path`
+            None => match addr.split_once(':') {
+                Some((scheme, rest)) if rest.starts_with('/') => Err(ParseAddressError { scheme: scheme.to_string() }),
+                _ => Ok(Address::Tcp(addr.to_string())),
+            },
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Address {
+    type Error = ParseAddressError;
+
+    fn try_from(addr: &'a str) -> Result<Address, ParseAddressError> {
+        addr.parse()
+    }
+}
+
+impl<'a> TryFrom<&'a String> for Address {
+    type Error = ParseAddressError;
+
+    fn try_from(addr: &'a String) -> Result<Address, ParseAddressError> {
+        addr.parse()
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = ParseAddressError;
+
+    fn try_from(addr: String) -> Result<Address, ParseAddressError> {
+        addr.parse()
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(addr: SocketAddr) -> Address {
+        Address::Tcp(addr.to_string())
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Address::Tcp(ref addr) => write!(f, "tcp://{}", addr),
+            Address::Unix(ref path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// An address with a scheme other than `tcp://` and `unix://`.
+#[derive (Clone, Debug, PartialEq, Eq)]
+pub struct ParseAddressError {
+    scheme: String,
+}
+
+impl ParseAddressError {
+    /// The scheme that is not supported
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+}
+
+impl fmt::Display for ParseAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unsupported OPC address scheme `{}`, expected `tcp://` or `unix://`", self.scheme)
+    }
+}
+
+impl Error for ParseAddressError {}
+
+impl From<Infallible> for ParseAddressError {
+    fn from(never: Infallible) -> ParseAddressError {
+        match never {}
+    }
+}
+
+#[test]
+fn should_roundtrip_through_display() {
+
+    for addr in &["tcp://localhost:7890", "unix:///tmp/opc.sock", "unix://relative.sock"] {
+        assert_eq!(addr.parse::<Address>().unwrap().to_string(), *addr);
+    }
+    assert_eq!("[::1]:7890".parse::<Address>().unwrap().to_string(), "tcp://[::1]:7890");
+
+}
+
+#[test]
+fn should_reject_unknown_schemes() {
+
+    for addr in &["udp://localhost:7890", "ws://localhost:7890", "unix:/tmp/opc.sock"] {
+        assert!(addr.parse::<Address>().is_err(), "{} parsed", addr);
+    }
+    assert_eq!("ws://localhost:7890".parse::<Address>().unwrap_err().scheme(), "ws");
+    assert!("localhost:7890".parse::<Address>().is_ok());
+
+}
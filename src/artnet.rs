@@ -0,0 +1,667 @@
+//! Art-Net bridging, for lighting gear that does not speak OPC.
+//!
+//! `Output` maps pixel ranges of OPC channels onto DMX universes and turns Set Pixel Colors data into `ArtDmx` packets.
+//! A universe holds 170 pixels, so longer ranges continue in the universes that follow.
+//! `Bridge` sends those packets over UDP and is a server `Handler`, so an OPC server can feed it directly:
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "tokio")]
+//! # mod example {
+//! use opc::artnet::{Bridge, Mapping, Output, PortAddress};
+//! use opc::Server;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     // 300 pixels of channel 1 fill universes 0:0:0 and 0:0:1
+//!     let output = Output::new().map(Mapping::new(1, PortAddress::new(0, 0, 0)).pixels(0, 300));
+//!     let bridge = Bridge::connect("2.255.255.255:6454", output).unwrap();
+//!
+//!     Server::new(bridge).listen("0.0.0.0:7890").await.unwrap();
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+//!
+//! `Input` goes the other way, assembling the universes a lighting desk sends into OPC frames,
+//! and holds frames back until the next `ArtSync` when the desk sends them:
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "tokio")]
+//! # mod example {
+//! use opc::artnet::{Input, Mapping, PortAddress, ARTNET_PORT};
+//! use opc::Client;
+//! use tokio::net::UdpSocket;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let input = Input::new().map(Mapping::new(1, PortAddress::new(0, 0, 0)).pixels(0, 300));
+//!     let socket = UdpSocket::bind(("0.0.0.0", ARTNET_PORT)).await.unwrap();
+//!
+//!     input.forward(socket, &Client::connect("127.0.0.1:7890")).await.unwrap();
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use crate::{Command, Message, BROADCAST_CHANNEL};
+
+/// Default Art-Net UDP port
+pub const ARTNET_PORT: u16 = 6454;
+
+/// Number of slots in a DMX universe.
+pub const UNIVERSE_SIZE: usize = 512;
+
+/// Number of whole pixels a DMX universe holds.
+pub const PIXELS_PER_UNIVERSE: usize = UNIVERSE_SIZE / 3;
+
+const ID: &[u8; 8] = b"Art-Net\0";
+const OP_DMX: u16 = 0x5000;
+const OP_SYNC: u16 = 0x5200;
+const PROTOCOL_VERSION: u16 = 14;
+const DMX_HEADER_LEN: usize = 18;
+const SYNC_LEN: usize = 14;
+
+/// How long a receiver keeps waiting for ArtSync before it outputs ArtDmx data right away again.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// The 15-bit address of a DMX universe: a 7-bit net, a 4-bit subnet and a 4-bit universe.
+#[derive (Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PortAddress(u16);
+
+impl PortAddress {
+    /// Create new Port Address, masking every part to its width
+    pub fn new(net: u8, subnet: u8, universe: u8) -> PortAddress {
+        PortAddress(((net as u16 & 0x7f) << 8) | ((subnet as u16 & 0x0f) << 4) | (universe as u16 & 0x0f))
+    }
+
+    /// Create new Port Address from its 15-bit value
+    pub fn from_u16(address: u16) -> PortAddress {
+        PortAddress(address & 0x7fff)
+    }
+
+    /// The 15-bit value
+    pub fn as_u16(self) -> u16 {
+        self.0
+    }
+
+    /// The net, from 0 to 127
+    pub fn net(self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    /// The subnet, from 0 to 15
+    pub fn subnet(self) -> u8 {
+        (self.0 >> 4) as u8 & 0x0f
+    }
+
+    /// The universe within the subnet, from 0 to 15
+    pub fn universe(self) -> u8 {
+        self.0 as u8 & 0x0f
+    }
+
+    /// The address `n` universes on, carrying into the subnet and net
+    pub fn offset(self, n: usize) -> PortAddress {
+        PortAddress::from_u16((self.0 as usize + n) as u16)
+    }
+}
+
+/// An ArtDmx packet, carrying the slots of one DMX universe.
+#[derive (Clone, Debug, PartialEq, Eq)]
+pub struct ArtDmx {
+    /// Orders packets from 1 to 255, or 0 when unused.
+    pub sequence: u8,
+    /// The physical input port the data came from.
+    pub physical: u8,
+    /// The universe the data is for.
+    pub address: PortAddress,
+    /// Up to 512 slots.
+    pub data: Vec<u8>,
+}
+
+impl ArtDmx {
+    /// Encode as a UDP payload, padding the data to the even length Art-Net requires
+    pub fn encode(&self) -> Vec<u8> {
+        let len = std::cmp::max(2, self.data.len().min(UNIVERSE_SIZE).next_multiple_of(2));
+        let mut packet = Vec::with_capacity(DMX_HEADER_LEN + len);
+        packet.extend_from_slice(ID);
+        packet.extend_from_slice(&OP_DMX.to_le_bytes());
+        packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        packet.push(self.sequence);
+        packet.push(self.physical);
+        packet.extend_from_slice(&self.address.0.to_le_bytes());
+        packet.extend_from_slice(&(len as u16).to_be_bytes());
+        packet.extend_from_slice(&self.data[..self.data.len().min(len)]);
+        packet.resize(DMX_HEADER_LEN + len, 0);
+        packet
+    }
+
+    /// Decode a UDP payload, returning `None` if it is not an ArtDmx packet
+    ///
+    /// Data beyond the end of the payload or past 512 slots is dropped.
+    pub fn decode(packet: &[u8]) -> Option<ArtDmx> {
+        if opcode(packet) != Some(OP_DMX) || packet.len() < DMX_HEADER_LEN {
+            return None;
+        }
+        let len = std::cmp::min(u16::from_be_bytes([packet[16], packet[17]]) as usize, UNIVERSE_SIZE);
+        let data = &packet[DMX_HEADER_LEN..];
+        Some(ArtDmx {
+            sequence: packet[12],
+            physical: packet[13],
+            address: PortAddress::from_u16(u16::from_le_bytes([packet[14], packet[15]])),
+            data: data[..std::cmp::min(len, data.len())].to_vec(),
+        })
+    }
+}
+
+/// The Art-Net packets a bridge understands.
+#[derive (Clone, Debug, PartialEq, Eq)]
+pub enum Packet {
+    /// DMX data for one universe.
+    Dmx(ArtDmx),
+    /// Output the ArtDmx data received since the last ArtSync.
+    Sync,
+}
+
+impl Packet {
+    /// Encode as a UDP payload
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            Packet::Dmx(ref dmx) => dmx.encode(),
+            Packet::Sync => {
+                let mut packet = Vec::with_capacity(SYNC_LEN);
+                packet.extend_from_slice(ID);
+                packet.extend_from_slice(&OP_SYNC.to_le_bytes());
+                packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+                packet.extend_from_slice(&[0, 0]);
+                packet
+            }
+        }
+    }
+
+    /// Decode a UDP payload, returning `None` for other and malformed packets
+    pub fn decode(packet: &[u8]) -> Option<Packet> {
+        match opcode(packet)? {
+            OP_DMX => ArtDmx::decode(packet).map(Packet::Dmx),
+            OP_SYNC if packet.len() >= SYNC_LEN => Some(Packet::Sync),
+            _ => None,
+        }
+    }
+}
+
+/// The OpCode of an Art-Net packet
+fn opcode(packet: &[u8]) -> Option<u16> {
+    match packet.split_first_chunk::<10>() {
+        Some((header, _)) if header.starts_with(ID) => Some(u16::from_le_bytes([header[8], header[9]])),
+        _ => None,
+    }
+}
+
+/// Maps a range of pixels on an OPC channel onto consecutive DMX universes.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    channel: u8,
+    first_pixel: usize,
+    pixels: usize,
+    address: PortAddress,
+    start: usize,
+}
+
+impl Mapping {
+    /// Create new Mapping of a channel's first 170 pixels onto a universe
+    pub fn new(channel: u8, address: PortAddress) -> Mapping {
+        Mapping {
+            channel,
+            first_pixel: 0,
+            pixels: PIXELS_PER_UNIVERSE,
+            address,
+            start: 0,
+        }
+    }
+
+    /// Map `count` pixels of the channel, starting at pixel `first`
+    pub fn pixels(mut self, first: usize, count: usize) -> Mapping {
+        self.first_pixel = first;
+        self.pixels = count;
+        self
+    }
+
+    /// Put the first pixel at a zero-based slot of the first universe instead of slot 0
+    ///
+    /// Following universes start at slot 0.
+    pub fn start(mut self, slot: usize) -> Mapping {
+        self.start = std::cmp::min(slot, UNIVERSE_SIZE);
+        self
+    }
+
+    /// Universe and slot of the `i`th mapped pixel, never splitting a pixel across universes
+    fn locate(&self, i: usize) -> (PortAddress, usize) {
+        let first = (UNIVERSE_SIZE - self.start) / 3;
+        if i < first {
+            (self.address, self.start + 3 * i)
+        } else {
+            let i = i - first;
+            (self.address.offset(1 + i / PIXELS_PER_UNIVERSE), 3 * (i % PIXELS_PER_UNIVERSE))
+        }
+    }
+
+    /// Indices of the mapped pixels that sit in a universe
+    fn pixels_in(&self, address: PortAddress) -> Range<usize> {
+        let first = (UNIVERSE_SIZE - self.start) / 3;
+        let range = match address.0.checked_sub(self.address.0) {
+            Some(0) => 0..first,
+            Some(n) => {
+                let n = n as usize - 1;
+                first + n * PIXELS_PER_UNIVERSE..first + (n + 1) * PIXELS_PER_UNIVERSE
+            }
+            None => 0..0,
+        };
+        std::cmp::min(range.start, self.pixels)..std::cmp::min(range.end, self.pixels)
+    }
+}
+
+/// Slots of a universe, and how many of them are mapped
+#[derive (Clone, Debug)]
+struct Universe {
+    slots: [u8; UNIVERSE_SIZE],
+    len: usize,
+}
+
+/// Maps OPC channels onto DMX universes and keeps the slots of every mapped universe.
+///
+/// Like a `Framebuffer`, a message only changes the pixels it carries,
+/// so universes shared by several mappings keep the rest of their slots.
+#[derive (Clone, Debug, Default)]
+pub struct Output {
+    mappings: Vec<Mapping>,
+    universes: BTreeMap<PortAddress, Universe>,
+    sequence: u8,
+}
+
+impl Output {
+    /// Create new Output without any mappings
+    pub fn new() -> Output {
+        Output::default()
+    }
+
+    /// Add a mapping
+    pub fn map(mut self, mapping: Mapping) -> Output {
+        for i in 0..mapping.pixels {
+            let (address, slot) = mapping.locate(i);
+            let universe = self.universes.entry(address).or_insert(Universe {
+                slots: [0; UNIVERSE_SIZE],
+                len: 0,
+            });
+            universe.len = std::cmp::max(universe.len, slot + 3);
+        }
+        self.mappings.push(mapping);
+        self
+    }
+
+    /// Apply the data of a Set Pixel Colors message, returning a packet for every universe it touched
+    ///
+    /// Messages on channel 0 apply to every mapping.
+    pub fn apply(&mut self, channel: u8, data: &[u8]) -> Vec<ArtDmx> {
+        let mut touched = Vec::new();
+        for mapping in &self.mappings {
+            if channel != BROADCAST_CHANNEL && channel != mapping.channel {
+                continue;
+            }
+            let pixels = data.chunks_exact(3).skip(mapping.first_pixel).take(mapping.pixels);
+            for (i, pixel) in pixels.enumerate() {
+                let (address, slot) = mapping.locate(i);
+                let universe = self.universes.get_mut(&address).expect("mapped universes are allocated");
+                universe.slots[slot..slot + 3].copy_from_slice(pixel);
+                if !touched.contains(&address) {
+                    touched.push(address);
+                }
+            }
+        }
+        if touched.is_empty() {
+            return Vec::new();
+        }
+
+        // Sequence numbers run from 1 to 255, 0 would turn reordering off
+        self.sequence = self.sequence % 255 + 1;
+        touched.sort_unstable();
+        touched.into_iter()
+            .map(|address| {
+                let universe = &self.universes[&address];
+                ArtDmx {
+                    sequence: self.sequence,
+                    physical: 0,
+                    address,
+                    data: universe.slots[..universe.len].to_vec(),
+                }
+            })
+            .collect()
+    }
+
+    /// Apply a message, returning a packet for every universe it touched
+    pub fn apply_message(&mut self, msg: &Message) -> Vec<ArtDmx> {
+        match msg.command {
+            Command::SetPixelColors { ref pixels } => self.apply(msg.channel, pixels.as_bytes()),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Sends the packets of an `Output` over UDP.
+#[derive (Debug)]
+pub struct Bridge {
+    socket: UdpSocket,
+    target: SocketAddr,
+    output: Output,
+}
+
+impl Bridge {
+    /// Bind a local socket that may broadcast, and send to `target`
+    pub fn connect<A: ToSocketAddrs>(target: A, output: Output) -> io::Result<Bridge> {
+        let target = match target.to_socket_addrs()?.next() {
+            Some(target) => target,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no address to send to")),
+        };
+        let socket = UdpSocket::bind(if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.set_broadcast(true)?;
+        Ok(Bridge::from_socket(socket, target, output))
+    }
+
+    /// Send to `target` through an existing socket
+    pub fn from_socket(socket: UdpSocket, target: SocketAddr, output: Output) -> Bridge {
+        Bridge { socket, target, output }
+    }
+
+    /// Send the universes a message touches
+    pub fn send(&mut self, msg: &Message) -> io::Result<()> {
+        for packet in self.output.apply_message(msg) {
+            self.socket.send_to(&packet.encode(), self.target)?;
+        }
+        Ok(())
+    }
+
+    /// The mappings and universe state
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+}
+
+/// Assembles the universes of ArtDmx packets into the pixels of OPC channels.
+///
+/// Until the first ArtSync arrives, every ArtDmx packet produces frames for the channels it touched.
+/// After that, frames wait for the next ArtSync so all universes of a frame change together,
+/// until no ArtSync has arrived for 4 seconds.
+#[derive (Clone, Debug, Default)]
+pub struct Input {
+    mappings: Vec<Mapping>,
+    channels: BTreeMap<u8, Vec<[u8; 3]>>,
+    changed: Vec<u8>,
+    last_sync: Option<Instant>,
+}
+
+impl Input {
+    /// Create new Input without any mappings
+    pub fn new() -> Input {
+        Input::default()
+    }
+
+    /// Add a mapping, growing its channel to hold the mapped pixels
+    pub fn map(mut self, mapping: Mapping) -> Input {
+        let pixels = self.channels.entry(mapping.channel).or_default();
+        let len = std::cmp::max(pixels.len(), mapping.first_pixel + mapping.pixels);
+        pixels.resize(len, [0; 3]);
+        self.mappings.push(mapping);
+        self
+    }
+
+    /// Handle a UDP payload received at `now`, returning the frames to send
+    ///
+    /// Packets other than ArtDmx and ArtSync are ignored.
+    pub fn receive(&mut self, packet: &[u8], now: Instant) -> Vec<Message> {
+        match Packet::decode(packet) {
+            Some(Packet::Dmx(ref dmx)) => self.apply(dmx, now),
+            Some(Packet::Sync) => self.sync(now),
+            None => Vec::new(),
+        }
+    }
+
+    /// Apply an ArtDmx packet received at `now`, returning frames unless they wait for an ArtSync
+    pub fn apply(&mut self, dmx: &ArtDmx, now: Instant) -> Vec<Message> {
+        for mapping in &self.mappings {
+            let pixels = self.channels.get_mut(&mapping.channel).expect("mapped channels are allocated");
+            for i in mapping.pixels_in(dmx.address) {
+                let (_, slot) = mapping.locate(i);
+                let Some(pixel) = dmx.data.get(slot..slot + 3) else { break };
+                pixels[mapping.first_pixel + i].copy_from_slice(pixel);
+                if !self.changed.contains(&mapping.channel) {
+                    self.changed.push(mapping.channel);
+                }
+            }
+        }
+        match self.last_sync {
+            Some(last_sync) if now.saturating_duration_since(last_sync) < SYNC_TIMEOUT => Vec::new(),
+            _ => self.frames(),
+        }
+    }
+
+    /// Handle an ArtSync received at `now`, returning frames for the channels changed since the last one
+    pub fn sync(&mut self, now: Instant) -> Vec<Message> {
+        self.last_sync = Some(now);
+        self.frames()
+    }
+
+    /// The pixels of a mapped channel
+    pub fn pixels(&self, channel: u8) -> Option<&[[u8; 3]]> {
+        self.channels.get(&channel).map(Vec::as_slice)
+    }
+
+    /// Frames for the changed channels, in channel order
+    fn frames(&mut self) -> Vec<Message> {
+        let channels = &self.channels;
+        self.changed.sort_unstable();
+        self.changed.drain(..)
+            .map(|channel| Message::from_pixels(channel, &channels[&channel]))
+            .collect()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Input {
+    /// Receive Art-Net packets on `socket` and queue the frames they produce on `client`
+    ///
+    /// Runs until receiving fails or the client stops.
+    pub async fn forward(mut self, socket: tokio::net::UdpSocket, client: &crate::Client) -> Result<(), crate::OpcError> {
+        let mut buf = vec![0; 1024];
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            for msg in self.receive(&buf[..len], Instant::now()) {
+                client.send(msg)?;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl crate::server::Handler for Bridge {
+    fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) {
+        self.on_pixel_data(channel, pixels.as_flattened());
+    }
+
+    fn on_pixel_data(&mut self, channel: u8, data: &[u8]) {
+        // Art-Net is fire and forget, a lost packet is replaced by the next frame
+        for packet in self.output.apply(channel, data) {
+            let _ = self.socket.send_to(&packet.encode(), self.target);
+        }
+    }
+}
+
+#[test]
+fn should_encode_artdmx_header() {
+
+    let packet = ArtDmx {
+        sequence: 7,
+        physical: 1,
+        address: PortAddress::new(1, 2, 3),
+        data: vec![10, 20, 30],
+    };
+
+    assert_eq!(packet.encode(), [
+        b'A', b'r', b't', b'-', b'N', b'e', b't', 0,
+        0x00, 0x50, 0, 14, 7, 1, 0x23, 0x01, 0, 4,
+        10, 20, 30, 0,
+    ]);
+
+}
+
+#[test]
+fn should_split_pixels_across_universes() {
+
+    let mut output = Output::new()
+        .map(Mapping::new(1, PortAddress::new(0, 0, 15)).pixels(10, 200).start(3))
+        .map(Mapping::new(2, PortAddress::new(0, 1, 0)).pixels(0, 2).start(100));
+    let data: Vec<u8> = (0..300 * 3).map(|i| (i / 3) as u8).collect();
+
+    let packets = output.apply(1, &data);
+
+    assert_eq!(packets.len(), 2);
+    // 169 pixels fit after slot 3, the rest continue in the next subnet
+    assert_eq!(packets[0].address, PortAddress::new(0, 0, 15));
+    assert_eq!(packets[0].data.len(), 3 + 169 * 3);
+    assert_eq!(&packets[0].data[..6], &[0, 0, 0, 10, 10, 10]);
+    assert_eq!(packets[1].address, PortAddress::new(0, 1, 0));
+    assert_eq!(packets[1].data.len(), 106);
+    assert_eq!(&packets[1].data[..3], &[179, 179, 179]);
+    assert_eq!(packets[1].sequence, 1);
+
+    let packets = output.apply(0, &[5, 6, 7]);
+    assert_eq!(packets.len(), 1);
+    assert_eq!(&packets[0].data[..3], &[179, 179, 179]);
+    assert_eq!(&packets[0].data[100..103], &[5, 6, 7]);
+    assert_eq!(packets[0].sequence, 2);
+
+}
+
+#[test]
+fn should_send_touched_universes() {
+
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let output = Output::new().map(Mapping::new(1, PortAddress::new(0, 0, 1)).pixels(0, 2));
+    let mut bridge = Bridge::connect(receiver.local_addr().unwrap(), output).unwrap();
+
+    bridge.send(&Message::from_pixels(2, &[[9; 3]])).unwrap();
+    bridge.send(&Message::from_pixels(1, &[[1, 2, 3]])).unwrap();
+
+    let mut buf = [0; 600];
+    let len = receiver.recv(&mut buf).unwrap();
+    assert_eq!(len, DMX_HEADER_LEN + 6);
+    assert_eq!(&buf[14..len], &[0x01, 0x00, 0, 6, 1, 2, 3, 0, 0, 0]);
+
+}
+
+#[test]
+fn should_decode_encoded_packets() {
+
+    let dmx = ArtDmx {
+        sequence: 3,
+        physical: 0,
+        address: PortAddress::new(2, 1, 4),
+        data: vec![1, 2, 3, 4],
+    };
+
+    assert_eq!(Packet::decode(&dmx.encode()), Some(Packet::Dmx(dmx.clone())));
+    assert_eq!(Packet::decode(&Packet::Sync.encode()), Some(Packet::Sync));
+    assert_eq!(Packet::Sync.encode().len(), SYNC_LEN);
+    // A truncated packet keeps the slots it carries
+    assert_eq!(ArtDmx::decode(&dmx.encode()[..DMX_HEADER_LEN + 2]).unwrap().data, [1, 2]);
+    assert_eq!(Packet::decode(b"Art-Net\0\x00\x20\x00\x0e"), None);
+    assert_eq!(Packet::decode(b"Not-Art\0\x00\x52\x00\x0e\x00\x00"), None);
+
+}
+
+#[test]
+fn should_assemble_universes_into_channels() {
+
+    let mut input = Input::new()
+        .map(Mapping::new(1, PortAddress::new(0, 0, 1)).pixels(0, 200).start(6))
+        .map(Mapping::new(2, PortAddress::new(0, 0, 2)).pixels(2, 1).start(300));
+    let now = Instant::now();
+    let dmx = |universe, data: Vec<u8>| ArtDmx {
+        sequence: 0,
+        physical: 0,
+        address: PortAddress::new(0, 0, universe),
+        data,
+    };
+
+    let frames = input.apply(&dmx(1, (0..UNIVERSE_SIZE).map(|i| (i / 3) as u8).collect()), now);
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].channel, 1);
+    assert_eq!(&input.pixels(1).unwrap()[..2], &[[2; 3], [3; 3]]);
+    assert_eq!(input.pixels(1).unwrap()[167], [169; 3]);
+    assert_eq!(input.pixels(1).unwrap()[168], [0; 3]);
+
+    // Universe 2 carries the rest of channel 1 and a pixel of channel 2
+    let frames = input.apply(&dmx(2, vec![9; 303]), now);
+    assert_eq!(frames.iter().map(|msg| msg.channel).collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(&input.pixels(1).unwrap()[168..], &[[9; 3]; 32][..]);
+    assert_eq!(input.pixels(2), Some(&[[0; 3], [0; 3], [9; 3]][..]));
+    assert_eq!(frames[1], Message::from_pixels(2, &[[0; 3], [0; 3], [9; 3]]));
+
+    assert!(input.apply(&dmx(3, vec![1; 512]), now).is_empty());
+
+}
+
+#[test]
+fn should_hold_frames_until_artsync() {
+
+    let mut input = Input::new().map(Mapping::new(1, PortAddress::new(0, 0, 0)).pixels(0, 1));
+    let dmx = Packet::Dmx(ArtDmx {
+        sequence: 0,
+        physical: 0,
+        address: PortAddress::new(0, 0, 0),
+        data: vec![4, 5, 6],
+    }).encode();
+    let sync = Packet::Sync.encode();
+    let start = Instant::now();
+
+    assert_eq!(input.receive(&dmx, start).len(), 1);
+    assert!(input.receive(&sync, start).is_empty());
+    assert!(input.receive(&dmx, start + Duration::from_secs(1)).is_empty());
+    assert_eq!(input.receive(&sync, start + Duration::from_secs(2)), [Message::from_pixels(1, &[[4, 5, 6]])]);
+
+    // Without ArtSync for a while, ArtDmx goes out right away again
+    assert_eq!(input.receive(&dmx, start + Duration::from_secs(6)).len(), 1);
+
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn should_forward_frames_to_client() {
+    use futures::StreamExt;
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = crate::Client::connect(listener.local_addr().unwrap());
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let target = socket.local_addr().unwrap();
+    let input = Input::new().map(Mapping::new(3, PortAddress::new(0, 0, 0)).pixels(0, 1));
+    tokio::spawn(async move { input.forward(socket, &client).await });
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = Framed::new(stream, crate::OpcCodec::new());
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let dmx = ArtDmx {
+        sequence: 0,
+        physical: 0,
+        address: PortAddress::new(0, 0, 0),
+        data: vec![7, 8, 9],
+    };
+    sender.send_to(&dmx.encode(), target).unwrap();
+
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::from_pixels(3, &[[7, 8, 9]]));
+
+}
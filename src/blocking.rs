@@ -0,0 +1,294 @@
+//! Blocking OPC client over TCP or Unix domain sockets, for render loops that do not run a reactor.
+//!
+//! ```rust,no_run
+//! use opc::blocking::Client;
+//!
+//! let mut client = Client::connect_default("192.168.1.230").unwrap();
+//! let mut pixels = vec![[0u8; 3]; 1000];
+//!
+//! loop {
+//!     for pixel in pixels.iter_mut() {
+//!         pixel[0] = pixel[0].wrapping_add(1);
+//!     }
+//!     client.set_pixels(0, &pixels).unwrap();
+//!     std::thread::sleep(std::time::Duration::from_millis(16));
+//! }
+//! ```
+
+use std::convert::TryInto;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::{Address, ColorOrders, MessageRef, OpcCodec, OpcError, ParseAddressError, DEFAULT_OPC_PORT};
+#[cfg(test)]
+use crate::{ColorOrder, Message};
+
+/// Blocking OPC Client Instance
+///
+/// Writes over TCP are sent with `TCP_NODELAY` set. If the server has dropped the connection,
+/// the client reconnects and retries the write once before reporting an error.
+#[derive (Debug)]
+pub struct Client {
+    target: Target,
+    stream: Option<Stream>,
+    codec: OpcCodec,
+    orders: ColorOrders,
+    write_timeout: Option<Duration>,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    /// Connect to an OPC server
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Client> {
+        Client::open(Target::Tcp(addr.to_socket_addrs()?.collect()))
+    }
+
+    /// Connect to an OPC server at a `tcp://` or `unix://` address
+    ///
+    /// Other schemes fail with `io::ErrorKind::InvalidInput`.
+    pub fn connect_address<A>(addr: A) -> io::Result<Client>
+        where A: TryInto<Address>,
+              A::Error: Into<ParseAddressError>
+    {
+        let addr = addr.try_into().map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.into()))?;
+        match addr {
+            Address::Tcp(addr) => Client::connect(addr),
+            Address::Unix(path) => Client::open(Target::Unix(path)),
+        }
+    }
+
+    fn open(target: Target) -> io::Result<Client> {
+        let mut client = Client {
+            target,
+            stream: None,
+            codec: OpcCodec::new(),
+            orders: ColorOrders::new(),
+            write_timeout: None,
+            buf: Vec::new(),
+        };
+        client.reconnect()?;
+        Ok(client)
+    }
+
+    /// Connect to an OPC server listening on the default port
+    pub fn connect_default(host: &str) -> io::Result<Client> {
+        Client::connect((host, DEFAULT_OPC_PORT as u16))
+    }
+
+    /// Use a custom codec to encode messages
+    pub fn with_codec(mut self, codec: OpcCodec) -> Client {
+        self.codec = codec;
+        self
+    }
+
+    /// Reorder the pixels of every channel for its strip before encoding
+    pub fn with_color_orders(mut self, orders: ColorOrders) -> Client {
+        self.orders = orders;
+        self
+    }
+
+    /// Set the timeout for writing a message, `None` blocking indefinitely
+    ///
+    /// A write that times out may leave a partial frame behind,
+    /// so the connection is dropped and reopened on the next send.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.write_timeout = timeout;
+        match self.stream {
+            Some(ref stream) => stream.set_write_timeout(timeout),
+            None => Ok(()),
+        }
+    }
+
+    /// Check if the client currently holds an open connection
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Drop the current connection, if any, and open a new one
+    pub fn reconnect(&mut self) -> io::Result<()> {
+        self.stream = None;
+        let stream = match self.target {
+            Target::Tcp(ref addrs) => {
+                let stream = TcpStream::connect(&addrs[..])?;
+                stream.set_nodelay(true)?;
+                Stream::Tcp(stream)
+            }
+            #[cfg(unix)]
+            Target::Unix(ref path) => Stream::Unix(UnixStream::connect(path)?),
+            #[cfg(not(unix))]
+            Target::Unix(_) => {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "Unix domain sockets are not supported"));
+            }
+        };
+        stream.set_write_timeout(self.write_timeout)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// Send a message, either a `&Message` or a borrowed `MessageRef`
+    pub fn send<'a, M: Into<MessageRef<'a>>>(&mut self, msg: M) -> Result<(), OpcError> {
+        self.buf.clear();
+        self.orders.encode_into(&self.codec, msg.into(), &mut self.buf)?;
+
+        if self.stream.is_none() {
+            self.reconnect()?;
+        }
+
+        match self.write_buf() {
+            Err(ref err) if is_disconnect(err) => {
+                self.reconnect()?;
+                self.write_buf().map_err(OpcError::from)
+            }
+            result => result.map_err(OpcError::from),
+        }
+    }
+
+    /// Set the first pixels of a channel
+    pub fn set_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) -> Result<(), OpcError> {
+        self.send(MessageRef::from_pixels(channel, pixels))
+    }
+
+    /// Send a System Exclusive message
+    pub fn sysex(&mut self, channel: u8, id: [u8; 2], data: &[u8]) -> Result<(), OpcError> {
+        self.send(MessageRef::from_data(channel, &id, data))
+    }
+
+    fn write_buf(&mut self) -> io::Result<()> {
+        let result = match self.stream {
+            Some(ref mut stream) => stream.write_all(&self.buf),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        };
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+}
+
+/// Where the client connects to
+#[derive (Debug)]
+enum Target {
+    Tcp(Vec<SocketAddr>),
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Unix(PathBuf),
+}
+
+/// An open connection
+#[derive (Debug)]
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(ref stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut stream) => stream.write_all(buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut stream) => stream.write_all(buf),
+        }
+    }
+}
+
+fn is_disconnect(err: &io::Error) -> bool {
+    matches!(err.kind(),
+             io::ErrorKind::BrokenPipe
+             | io::ErrorKind::ConnectionReset
+             | io::ErrorKind::ConnectionAborted
+             | io::ErrorKind::NotConnected)
+}
+
+#[cfg(test)]
+fn read_message(stream: &mut TcpStream) -> Message {
+    use std::io::Read;
+
+    let mut header = [0; crate::parser::HEADER_LEN];
+    stream.read_exact(&mut header).unwrap();
+    let mut frame = header.to_vec();
+    frame.resize(crate::parser::frame_len(&header).unwrap(), 0);
+    stream.read_exact(&mut frame[header.len()..]).unwrap();
+    crate::parser::parse_frame(&frame).unwrap().0.unwrap().to_message()
+}
+
+#[test]
+fn should_send_messages() {
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = Client::connect(listener.local_addr().unwrap())
+        .unwrap()
+        .with_color_orders(ColorOrders::new().channel(5, ColorOrder::Grb));
+    let (mut stream, _) = listener.accept().unwrap();
+
+    client.set_pixels(3, &[[1, 2, 3], [4, 5, 6]]).unwrap();
+    client.sysex(4, [0, 1], &[7, 8]).unwrap();
+    client.set_pixels(5, &[[1, 2, 3]]).unwrap();
+
+    assert_eq!(read_message(&mut stream), Message::from_pixels(3, &[[1, 2, 3], [4, 5, 6]]));
+    assert_eq!(read_message(&mut stream), Message::from_data(4, &[0, 1], &[7, 8]));
+    assert_eq!(read_message(&mut stream), Message::from_pixels(5, &[[2, 1, 3]]));
+
+}
+
+#[test]
+fn should_reconnect_after_disconnect() {
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = Client::connect(listener.local_addr().unwrap()).unwrap();
+    drop(listener.accept().unwrap());
+
+    // The first writes may still be buffered before the reset is noticed
+    listener.set_nonblocking(true).unwrap();
+    let msg = Message::from_pixels(1, &[[9, 9, 9]]);
+    let mut stream = None;
+    for _ in 0..100 {
+        client.send(&msg).unwrap();
+        if let Ok((accepted, _)) = listener.accept() {
+            stream = Some(accepted);
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut stream = stream.expect("client did not reconnect");
+    stream.set_nonblocking(false).unwrap();
+    assert_eq!(read_message(&mut stream), msg);
+
+}
+
+#[cfg(unix)]
+#[test]
+fn should_send_over_unix_socket() {
+    use std::io::Read;
+    use std::os::unix::net::UnixListener;
+
+    let dir = std::env::temp_dir().join(format!("opc-blocking-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("opc.sock");
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let mut client = Client::connect_address(format!("unix://{}", path.display())).unwrap();
+    let (mut stream, _) = listener.accept().unwrap();
+    client.set_pixels(2, &[[1, 2, 3]]).unwrap();
+
+    let mut frame = [0; 7];
+    stream.read_exact(&mut frame).unwrap();
+    assert_eq!(frame, [2, 0, 0, 3, 1, 2, 3]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+}
@@ -0,0 +1,461 @@
+//! Async OPC client that keeps its connection alive.
+//!
+//! LED controllers reboot often, so `Client` runs its connection on a background task,
+//! reconnects with exponential backoff whenever the connection drops,
+//! and re-sends the latest frame of every channel once it is back.
+//! Messages wait in a `FrameQueue`, so a slow server only ever receives the newest frame of a channel.
+//!
+//! ```rust,no_run
+//! use opc::Client;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let client = Client::connect("192.168.1.230:7890");
+//!     let mut pixels = vec![[0u8; 3]; 1000];
+//!
+//!     loop {
+//!         for pixel in pixels.iter_mut() {
+//!             pixel[0] = pixel[0].wrapping_add(1);
+//!         }
+//!         client.set_pixels(0, &pixels).unwrap();
+//!         tokio::time::sleep(std::time::Duration::from_millis(16)).await;
+//!     }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio_util::codec::Framed;
+
+use crate::queue::FrameQueue;
+use crate::{Address, ColorOrders, Command, Message, OpcCodec, OpcError, ParseAddressError};
+
+/// Describes the state of a `Client`'s connection.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Trying to open a connection.
+    Connecting,
+    /// Connected, messages are being sent.
+    Connected,
+    /// The connection dropped or could not be opened, waiting before the next attempt.
+    ///
+    /// `Client::last_error` tells why.
+    Disconnected,
+}
+
+/// Configures and spawns a `Client`.
+#[derive (Clone, Debug)]
+pub struct Builder {
+    addr: Result<Address, ParseAddressError>,
+    codec: OpcCodec,
+    orders: ColorOrders,
+    min_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Builder {
+    /// Create new Builder for a client of the server at `addr`, such as `"tcp://127.0.0.1:7890"` or `"unix:///run/opc.sock"`
+    ///
+    /// A client with an unsupported address scheme stops right away, with the reason in `Client::last_error`.
+    pub fn new<A>(addr: A) -> Builder
+        where A: TryInto<Address>,
+              A::Error: Into<ParseAddressError>
+    {
+        Builder {
+            addr: addr.try_into().map_err(Into::into),
+            codec: OpcCodec::new(),
+            orders: ColorOrders::new(),
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+
+    /// Use a custom codec to encode messages
+    pub fn codec(mut self, codec: OpcCodec) -> Builder {
+        self.codec = codec;
+        self
+    }
+
+    /// Reorder the pixels of every channel for its strip before queueing
+    pub fn color_orders(mut self, orders: ColorOrders) -> Builder {
+        self.orders = orders;
+        self
+    }
+
+    /// Set the delay before the first reconnect attempt, doubling after every failure up to `max`
+    pub fn backoff(mut self, min: Duration, max: Duration) -> Builder {
+        self.min_backoff = min;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Spawn the connection task on the current tokio runtime
+    pub fn spawn(self) -> Client {
+        let queue = Arc::new(Mutex::new(FrameQueue::new()));
+        let (wake_tx, wake_rx) = mpsc::channel(1);
+        let (state_tx, state_rx) = watch::channel(ConnectionState::Connecting);
+        let pending = Pending {
+            queue: queue.clone(),
+            wake: wake_rx,
+        };
+        let status = Status {
+            state: state_tx,
+            error: Arc::new(Mutex::new(None)),
+        };
+        let orders = Arc::new(self.orders.clone());
+        let codec = self.codec.clone();
+        let error = status.error.clone();
+        tokio::spawn(self.run(pending, status));
+        Client {
+            queue,
+            codec,
+            orders,
+            wake: wake_tx,
+            state: state_rx,
+            error,
+        }
+    }
+
+    async fn run(self, mut pending: Pending, status: Status) {
+        let addr = match self.addr {
+            Ok(ref addr) => addr,
+            Err(ref err) => {
+                // No retry can fix the address, so stop and let `send` fail
+                status.disconnected(io::Error::new(io::ErrorKind::InvalidInput, err.clone()).into());
+                return;
+            }
+        };
+        // Latest Set Pixel Colors message of every channel, re-sent after reconnecting
+        let mut latest = BTreeMap::new();
+        let mut backoff = self.min_backoff;
+
+        loop {
+            status.state.send_replace(ConnectionState::Connecting);
+
+            let result = match *addr {
+                Address::Tcp(ref addr) => match TcpStream::connect(&**addr).await {
+                    Ok(socket) => {
+                        let _ = socket.set_nodelay(true);
+                        backoff = self.min_backoff;
+                        self.session(socket, &mut pending, &mut latest, &status).await
+                    }
+                    Err(err) => Err(err.into()),
+                },
+                #[cfg(unix)]
+                Address::Unix(ref path) => match tokio::net::UnixStream::connect(path).await {
+                    Ok(socket) => {
+                        backoff = self.min_backoff;
+                        self.session(socket, &mut pending, &mut latest, &status).await
+                    }
+                    Err(err) => Err(err.into()),
+                },
+                #[cfg(not(unix))]
+                Address::Unix(_) => {
+                    Err(io::Error::new(io::ErrorKind::Unsupported, "Unix domain sockets are not supported").into())
+                }
+            };
+            match result {
+                Ok(()) => return,
+                Err(err) => status.disconnected(err),
+            }
+
+            if !wait(&mut pending, backoff).await {
+                return;
+            }
+            backoff = std::cmp::min(backoff * 2, self.max_backoff);
+        }
+    }
+
+    /// Send over a new connection. Returns once the client is dropped, or with the error that ended the connection.
+    async fn session<S>(&self,
+                        socket: S,
+                        pending: &mut Pending,
+                        latest: &mut BTreeMap<u8, Message>,
+                        status: &Status)
+                        -> Result<(), OpcError>
+        where S: AsyncRead + AsyncWrite + Unpin
+    {
+        status.connected();
+        let mut transport = Framed::new(socket, self.codec.clone());
+        send_all(&mut transport, pending, latest).await
+    }
+}
+
+/// Async OPC Client Instance
+///
+/// Messages are queued and sent by a background task, which stops once every clone of the client is dropped.
+/// Only the latest queued Set Pixel Colors message of each channel is kept, other messages are all sent in order.
+#[derive (Clone, Debug)]
+pub struct Client {
+    queue: Arc<Mutex<FrameQueue>>,
+    codec: OpcCodec,
+    orders: Arc<ColorOrders>,
+    wake: mpsc::Sender<()>,
+    state: watch::Receiver<ConnectionState>,
+    error: Arc<Mutex<Option<Arc<OpcError>>>>,
+}
+
+impl Client {
+    /// Spawn a client of the server at `addr` with the default settings
+    pub fn connect<A>(addr: A) -> Client
+        where A: TryInto<Address>,
+              A::Error: Into<ParseAddressError>
+    {
+        Builder::new(addr).spawn()
+    }
+
+    /// Queue a message
+    ///
+    /// Messages the codec cannot encode are rejected here rather than by the background task.
+    pub fn send(&self, msg: Message) -> Result<(), OpcError> {
+        if self.is_stopped() {
+            return Err(OpcError::Io(io::Error::new(io::ErrorKind::NotConnected, "OPC client task stopped")));
+        }
+        self.codec.check(msg.as_ref())?;
+        let msg = self.orders.to_wire(msg);
+        self.queue.lock().unwrap().push(msg);
+        // A full channel means the task has a wake up pending already
+        let _ = self.wake.try_send(());
+        Ok(())
+    }
+
+    /// Queue new values for the first pixels of a channel
+    pub fn set_pixels(&self, channel: u8, pixels: &[[u8; 3]]) -> Result<(), OpcError> {
+        self.send(Message::from_pixels(channel, pixels))
+    }
+
+    /// Queue a System Exclusive message
+    pub fn sysex(&self, channel: u8, id: [u8; 2], data: &[u8]) -> Result<(), OpcError> {
+        self.send(Message::from_data(channel, &id, data))
+    }
+
+    /// Current state of the connection
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Watch the connection state change
+    pub fn events(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Why the last connection attempt failed or the last connection dropped, until the next connection opens
+    pub fn last_error(&self) -> Option<Arc<OpcError>> {
+        self.error.lock().unwrap().clone()
+    }
+
+    /// Number of frames replaced by a newer frame of the same channel before they were sent
+    pub fn dropped_frames(&self) -> u64 {
+        self.queue.lock().unwrap().dropped()
+    }
+
+    /// Whether the background task stopped, after which every message is rejected
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.wake.is_closed()
+    }
+}
+
+/// The task's side of the queue
+struct Pending {
+    queue: Arc<Mutex<FrameQueue>>,
+    wake: mpsc::Receiver<()>,
+}
+
+impl Pending {
+    fn pop(&self) -> Option<Message> {
+        self.queue.lock().unwrap().pop()
+    }
+
+    fn has_pixels(&self, channel: u8) -> bool {
+        self.queue.lock().unwrap().has_pixels(channel)
+    }
+}
+
+/// The task's side of the connection state
+struct Status {
+    state: watch::Sender<ConnectionState>,
+    error: Arc<Mutex<Option<Arc<OpcError>>>>,
+}
+
+impl Status {
+    fn connected(&self) {
+        *self.error.lock().unwrap() = None;
+        self.state.send_replace(ConnectionState::Connected);
+    }
+
+    /// Record the error before announcing it, so watchers of the state can read it
+    fn disconnected(&self, err: OpcError) {
+        *self.error.lock().unwrap() = Some(Arc::new(err));
+        self.state.send_replace(ConnectionState::Disconnected);
+    }
+}
+
+/// Wait out a backoff delay. Returns `false` once the client is dropped.
+async fn wait(pending: &mut Pending, delay: Duration) -> bool {
+    let sleep = tokio::time::sleep(delay);
+    tokio::pin!(sleep);
+    loop {
+        tokio::select! {
+            _ = &mut sleep => return true,
+            wake = pending.wake.recv() => if wake.is_none() {
+                return false;
+            },
+        }
+    }
+}
+
+/// Send the latest frames, then queued messages until the client is dropped or the connection fails.
+async fn send_all<S>(transport: &mut Framed<S, OpcCodec>,
+                     pending: &mut Pending,
+                     latest: &mut BTreeMap<u8, Message>)
+                     -> Result<(), OpcError>
+    where S: AsyncRead + AsyncWrite + Unpin
+{
+    // Frames still queued are newer than the ones the previous connection sent
+    for msg in latest.values() {
+        if !pending.has_pixels(msg.channel) {
+            transport.feed(msg.clone()).await?;
+        }
+    }
+    SinkExt::<Message>::flush(transport).await?;
+
+    loop {
+        while let Some(msg) = pending.pop() {
+            // A message that cannot be encoded must not end the connection or be replayed after reconnecting
+            if transport.codec().check(msg.as_ref()).is_err() {
+                continue;
+            }
+            if let Command::SetPixelColors { .. } = msg.command {
+                latest.insert(msg.channel, msg.clone());
+            }
+            transport.send(msg).await?;
+        }
+
+        tokio::select! {
+            wake = pending.wake.recv() => if wake.is_none() {
+                return Ok(());
+            },
+            // Servers do not reply, so anything here means the connection is closing
+            _ = transport.next() => {
+                return Err(io::Error::from(io::ErrorKind::ConnectionReset).into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+async fn wait_for(events: &mut watch::Receiver<ConnectionState>, state: ConnectionState) {
+    events.wait_for(|s| *s == state).await.unwrap();
+}
+
+#[tokio::test]
+async fn should_resend_latest_frames_after_reconnect() {
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = Builder::new(listener.local_addr().unwrap().to_string())
+        .backoff(Duration::from_millis(10), Duration::from_millis(50))
+        .spawn();
+    let mut events = client.events();
+
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut server = Framed::new(socket, OpcCodec::new());
+    wait_for(&mut events, ConnectionState::Connected).await;
+
+    client.set_pixels(1, &[[1, 1, 1]]).unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::from_pixels(1, &[[1, 1, 1]]));
+    client.set_pixels(2, &[[2, 2, 2]]).unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::from_pixels(2, &[[2, 2, 2]]));
+    client.set_pixels(1, &[[3, 3, 3]]).unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::from_pixels(1, &[[3, 3, 3]]));
+
+    // Drop the connection and expect the latest frame of each channel on the next one
+    drop(server);
+
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut server = Framed::new(socket, OpcCodec::new());
+
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::from_pixels(1, &[[3, 3, 3]]));
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::from_pixels(2, &[[2, 2, 2]]));
+
+}
+
+#[tokio::test]
+async fn should_reject_oversized_frames_before_queueing() {
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = Client::connect(listener.local_addr().unwrap());
+
+    match client.set_pixels(1, &[[0; 3]; 25_000]) {
+        Err(OpcError::MessageTooLarge(75_000)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    client.set_pixels(2, &[[2, 2, 2]]).unwrap();
+
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut server = Framed::new(socket, OpcCodec::new());
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::from_pixels(2, &[[2, 2, 2]]));
+
+}
+
+#[tokio::test]
+async fn should_back_off_while_server_is_down() {
+
+    // Reserve a port nobody listens on
+    let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let client = Builder::new(addr.to_string())
+        .backoff(Duration::from_millis(10), Duration::from_millis(20))
+        .spawn();
+    let mut events = client.events();
+
+    wait_for(&mut events, ConnectionState::Disconnected).await;
+    match client.last_error().as_deref() {
+        Some(OpcError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused),
+        other => panic!("unexpected error: {:?}", other),
+    }
+    wait_for(&mut events, ConnectionState::Connecting).await;
+    assert!(client.set_pixels(1, &[[1, 2, 3]]).is_ok());
+
+}
+
+#[tokio::test]
+async fn should_stop_on_unsupported_scheme() {
+
+    let client = Client::connect("udp://127.0.0.1:7890");
+    let mut events = client.events();
+
+    wait_for(&mut events, ConnectionState::Disconnected).await;
+    assert!(client.last_error().unwrap().to_string().contains("`udp`"));
+    // The task ends without retrying
+    while events.changed().await.is_ok() {}
+    assert!(client.set_pixels(1, &[[1, 2, 3]]).is_err());
+
+}
+
+#[tokio::test]
+async fn should_only_send_newest_queued_frame() {
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = Client::connect(listener.local_addr().unwrap().to_string());
+
+    // Frames queued before the task gets to run coalesce
+    for i in 0..5 {
+        client.set_pixels(1, &[[i; 3]]).unwrap();
+    }
+    assert_eq!(client.dropped_frames(), 4);
+
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut server = Framed::new(socket, OpcCodec::new());
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::from_pixels(1, &[[4; 3]]));
+
+}
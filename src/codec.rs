@@ -0,0 +1,225 @@
+#[cfg(any(feature = "tokio", feature = "legacy"))]
+use bytes::Bytes;
+
+#[cfg(any(feature = "tokio", feature = "legacy"))]
+use crate::parser::{frame_len, parse_frame};
+use crate::parser::{header, CommandRef, MessageRef, HEADER_LEN};
+#[cfg(any(feature = "tokio", feature = "legacy"))]
+use crate::{Command, Message, Pixels};
+use crate::OpcError;
+use crate::{MAX_PIXELS_PER_MESSAGE, SET_PIXEL_COLORS, SYS_EXCLUSIVE};
+
+#[cfg(feature = "legacy")]
+mod legacy;
+#[cfg(feature = "tokio")]
+mod tokio;
+
+/// Describes how the encoder treats messages with more than 65535 bytes of data.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OversizePolicy {
+    /// Fail with `OpcError::MessageTooLarge` and write nothing.
+    Reject,
+    /// Split Set Pixel Colors messages into several frames on the same channel,
+    /// each holding at most 21845 pixels. System Exclusive messages are still rejected.
+    ///
+    /// Every frame addresses the channel from its first pixel,
+    /// so this is only useful for receivers that expect a long strand in pieces.
+    Split,
+}
+
+/// Describes how the decoder treats frames whose command it does not interpret.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownCommandPolicy {
+    /// Fail with `OpcError::UnknownCommand`. The frame is still consumed.
+    Fail,
+    /// Drop the frame and continue with the next one.
+    Skip,
+    /// Yield the frame as `Command::Unknown`.
+    PassThrough,
+}
+
+/// Open Pixel Codec Instance
+#[derive (Clone, Debug)]
+pub struct OpcCodec {
+    oversize: OversizePolicy,
+    unknown: UnknownCommandPolicy,
+}
+
+impl OpcCodec {
+    /// Create new Codec Instance that rejects oversized messages and fails on unknown commands
+    pub fn new() -> OpcCodec {
+        OpcCodec {
+            oversize: OversizePolicy::Reject,
+            unknown: UnknownCommandPolicy::Fail,
+        }
+    }
+
+    /// Set how messages with more than 65535 bytes of data are encoded
+    pub fn oversize_policy(mut self, policy: OversizePolicy) -> OpcCodec {
+        self.oversize = policy;
+        self
+    }
+
+    /// Set how frames with an unknown command are decoded
+    pub fn unknown_command_policy(mut self, policy: UnknownCommandPolicy) -> OpcCodec {
+        self.unknown = policy;
+        self
+    }
+
+    /// Decode the next message, waiting for more data or skipping frames as needed.
+    #[cfg(any(feature = "tokio", feature = "legacy"))]
+    pub(crate) fn decode_from<B: Buffer>(&self, src: &mut B) -> Result<Option<Message>, OpcError> {
+        self.decode_next(src, true)
+    }
+
+    /// Decode the next message of a buffer that will not grow, such as a datagram.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn decode_complete<B: Buffer>(&self, src: &mut B) -> Result<Option<Message>, OpcError> {
+        self.decode_next(src, false)
+    }
+
+    #[cfg(any(feature = "tokio", feature = "legacy"))]
+    fn decode_next<B: Buffer>(&self, src: &mut B, reserve: bool) -> Result<Option<Message>, OpcError> {
+        loop {
+            let len = match frame_len(src) {
+                Some(len) if len <= src.len() => len,
+                Some(len) => {
+                    // Make room for the rest of the frame up front
+                    if reserve {
+                        src.reserve(len - src.len());
+                    }
+                    return Ok(None);
+                }
+                None => return Ok(None),
+            };
+
+            // Take the frame before checking it, so a bad frame never stalls the stream
+            let frame = src.take(len);
+            let (msg, _) = parse_frame(&frame).expect("frame is complete");
+
+            if let Some(msg) = self.accept(msg?, &frame)? {
+                return Ok(Some(msg));
+            }
+        }
+    }
+
+    /// Apply the unknown command policy to a parsed message. Returns `None` for frames that are skipped.
+    #[cfg(any(feature = "tokio", feature = "legacy"))]
+    fn accept(&self, msg: MessageRef, frame: &Bytes) -> Result<Option<Message>, OpcError> {
+        match (msg.command, self.unknown) {
+            (CommandRef::SetPixelColors { .. }, _) => {
+                // Share the frame rather than copying the pixels out of it
+                Ok(Some(Message {
+                    channel: msg.channel,
+                    command: Command::SetPixelColors { pixels: Pixels::from_bytes(frame.slice(HEADER_LEN..)) },
+                }))
+            }
+            (CommandRef::Unknown { command, .. }, UnknownCommandPolicy::Fail) => Err(OpcError::UnknownCommand(command)),
+            (CommandRef::Unknown { .. }, UnknownCommandPolicy::Skip) => Ok(None),
+            _ => Ok(Some(msg.to_message())),
+        }
+    }
+
+    /// Check that a message can be encoded, without encoding it.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn check(&self, msg: MessageRef) -> Result<(), OpcError> {
+        match (self.oversize, msg.command) {
+            _ if msg.is_valid() => Ok(()),
+            (OversizePolicy::Split, CommandRef::SetPixelColors { .. }) => Ok(()),
+            _ => Err(OpcError::MessageTooLarge(msg.len())),
+        }
+    }
+
+    /// Encode a message as one or more frames.
+    pub(crate) fn encode_into<B: Buffer>(&self, msg: MessageRef, dst: &mut B) -> Result<(), OpcError> {
+
+        let ser_len = msg.len();
+
+        if !msg.is_valid() {
+            return match (self.oversize, msg.command) {
+                (OversizePolicy::Split, CommandRef::SetPixelColors { data }) => {
+                    let frames = ser_len.div_ceil(MAX_PIXELS_PER_MESSAGE * 3);
+                    dst.reserve(HEADER_LEN * frames + ser_len);
+                    for chunk in data.chunks(MAX_PIXELS_PER_MESSAGE * 3) {
+                        put_pixels(dst, msg.channel, chunk);
+                    }
+                    Ok(())
+                }
+                _ => Err(OpcError::MessageTooLarge(ser_len)),
+            };
+        }
+
+        dst.reserve(HEADER_LEN + ser_len);
+
+        match msg.command {
+            CommandRef::SetPixelColors { data } => put_pixels(dst, msg.channel, data),
+            CommandRef::SystemExclusive { id, data } => {
+                put_header(dst, msg.channel, SYS_EXCLUSIVE, ser_len);
+
+                // Insert Data
+                dst.put_slice(&id);
+                dst.put_slice(data);
+            }
+            CommandRef::Unknown { command, data } => {
+                put_header(dst, msg.channel, command, ser_len);
+                dst.put_slice(data);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for OpcCodec {
+    fn default() -> OpcCodec {
+        OpcCodec::new()
+    }
+}
+
+/// The buffer operations the codec needs, so one implementation serves every `bytes` version.
+pub(crate) trait Buffer: std::ops::Deref<Target = [u8]> {
+    fn reserve(&mut self, additional: usize);
+    fn put_slice(&mut self, src: &[u8]);
+    /// Remove the first `len` bytes, sharing them without a copy where the buffer allows it.
+    #[cfg(any(feature = "tokio", feature = "legacy"))]
+    fn take(&mut self, len: usize) -> Bytes;
+}
+
+impl Buffer for bytes::BytesMut {
+    fn reserve(&mut self, additional: usize) {
+        bytes::BytesMut::reserve(self, additional)
+    }
+
+    fn put_slice(&mut self, src: &[u8]) {
+        self.extend_from_slice(src)
+    }
+
+    #[cfg(any(feature = "tokio", feature = "legacy"))]
+    fn take(&mut self, len: usize) -> Bytes {
+        self.split_to(len).freeze()
+    }
+}
+
+impl Buffer for Vec<u8> {
+    fn reserve(&mut self, additional: usize) {
+        Vec::reserve(self, additional)
+    }
+
+    fn put_slice(&mut self, src: &[u8]) {
+        self.extend_from_slice(src)
+    }
+
+    #[cfg(any(feature = "tokio", feature = "legacy"))]
+    fn take(&mut self, len: usize) -> Bytes {
+        self.drain(..len).collect::<Vec<_>>().into()
+    }
+}
+
+fn put_header<B: Buffer>(dst: &mut B, channel: u8, command: u8, len: usize) {
+    dst.put_slice(&header(channel, command, len as u16));
+}
+
+fn put_pixels<B: Buffer>(dst: &mut B, channel: u8, data: &[u8]) {
+    put_header(dst, channel, SET_PIXEL_COLORS, data.len());
+    dst.put_slice(data);
+}
@@ -0,0 +1,77 @@
+use bytes_04::BytesMut;
+use tokio_io::codec::{Decoder, Encoder};
+
+use super::{Buffer, OpcCodec};
+use crate::{Message, OpcError};
+
+impl Buffer for BytesMut {
+    fn reserve(&mut self, additional: usize) {
+        BytesMut::reserve(self, additional)
+    }
+
+    fn put_slice(&mut self, src: &[u8]) {
+        self.extend_from_slice(src)
+    }
+
+    fn take(&mut self, len: usize) -> bytes::Bytes {
+        bytes::Bytes::copy_from_slice(&self.split_to(len))
+    }
+}
+
+impl Decoder for OpcCodec {
+    type Item = Message;
+    type Error = OpcError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, OpcError> {
+        self.decode_from(src)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, OpcError> {
+        match self.decode(buf)? {
+            Some(msg) => Ok(Some(msg)),
+            None if buf.is_empty() => Ok(None),
+            None => Err(OpcError::Truncated),
+        }
+    }
+}
+
+impl Encoder for OpcCodec {
+    type Item = Message;
+    type Error = OpcError;
+
+    fn encode(&mut self, msg: Self::Item, dst: &mut BytesMut) -> Result<(), OpcError> {
+        self.encode_into(msg.as_ref(), dst)
+    }
+}
+
+#[test]
+fn should_roundtrip_legacy_pixel_command() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::new();
+    let test_msg = Message::from_pixels(4, &[[9; 3]; 10]);
+
+    assert!(codec.encode(test_msg.clone(), &mut buf).is_ok());
+
+    let recv_msg = codec.decode(&mut buf).unwrap().unwrap();
+
+    assert_eq!(test_msg, recv_msg);
+    assert!(buf.is_empty());
+
+}
+
+#[test]
+fn should_wait_for_partial_legacy_frame() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::from(&[4u8, 0, 0x10, 0x00, 1, 2][..]);
+
+    assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    assert!(buf.capacity() >= 4 + 0x1000);
+
+    match codec.decode_eof(&mut buf) {
+        Err(OpcError::Truncated) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+}
@@ -0,0 +1,359 @@
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::OpcCodec;
+#[cfg(test)]
+use super::{OversizePolicy, UnknownCommandPolicy};
+#[cfg(test)]
+use crate::{Command, MAX_MESSAGE_SIZE, MAX_PIXELS_PER_MESSAGE, SET_PIXEL_COLORS, SYS_EXCLUSIVE};
+use crate::{Message, MessageRef, OpcError};
+
+impl Decoder for OpcCodec {
+    type Item = Message;
+    type Error = OpcError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, OpcError> {
+        self.decode_from(src)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, OpcError> {
+        match self.decode(buf)? {
+            Some(msg) => Ok(Some(msg)),
+            None if buf.is_empty() => Ok(None),
+            None => Err(OpcError::Truncated),
+        }
+    }
+}
+
+impl Encoder<Message> for OpcCodec {
+    type Error = OpcError;
+
+    fn encode(&mut self, msg: Message, dst: &mut BytesMut) -> Result<(), OpcError> {
+        self.encode_into(msg.as_ref(), dst)
+    }
+}
+
+impl<'a> Encoder<MessageRef<'a>> for OpcCodec {
+    type Error = OpcError;
+
+    fn encode(&mut self, msg: MessageRef<'a>, dst: &mut BytesMut) -> Result<(), OpcError> {
+        self.encode_into(msg, dst)
+    }
+}
+
+#[test]
+fn should_roundtrip_pixel_command() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::new();
+    let test_msg = Message {
+        channel: 4,
+        command: Command::SetPixelColors { pixels: vec![[9; 3]; 10].into() },
+    };
+
+    assert!(codec.encode(test_msg.clone(), &mut buf).is_ok());
+
+    let recv_msg = codec.decode(&mut buf).unwrap().unwrap();
+
+    assert_eq!(test_msg, recv_msg);
+
+}
+
+#[test]
+fn server_roundtrip_system_command() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::new();
+    let test_msg = Message {
+        channel: 4,
+        command: Command::SystemExclusive {
+            id: [0; 2],
+            data: vec![8; 10],
+        },
+    };
+
+    assert!(codec.encode(test_msg.clone(), &mut buf).is_ok());
+
+    let recv_msg = codec.decode(&mut buf).unwrap().unwrap();
+
+    assert_eq!(test_msg, recv_msg);
+
+}
+
+#[cfg(test)]
+fn encode_all(msgs: &[Message]) -> BytesMut {
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::new();
+    for msg in msgs {
+        codec.encode(msg.clone(), &mut buf).unwrap();
+    }
+    buf
+}
+
+#[cfg(test)]
+fn sample_messages() -> Vec<Message> {
+    vec![
+        Message::from_pixels(1, &[[1, 2, 3]; 100]),
+        Message::from_data(2, &[0, 1], &[4, 5, 6]),
+        Message::from_pixels(0, &[]),
+        Message::from_pixels(255, &[[7, 8, 9]; 3000]),
+    ]
+}
+
+#[test]
+fn should_wait_for_partial_header() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::from(&[4u8, SET_PIXEL_COLORS, 0][..]);
+
+    assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    assert_eq!(buf.len(), 3);
+
+}
+
+#[test]
+fn should_reserve_announced_frame_length() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::from(&[4u8, SET_PIXEL_COLORS, 0x10, 0x00, 1, 2][..]);
+
+    assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    assert_eq!(buf.len(), 6);
+    assert!(buf.capacity() >= 4 + 0x1000);
+
+}
+
+#[test]
+fn should_not_reserve_for_incomplete_end_of_datagram() {
+
+    let codec = OpcCodec::new();
+    let mut buf = BytesMut::from(&[1u8, SET_PIXEL_COLORS, 0, 3, 1, 2, 3, 4, SET_PIXEL_COLORS, 0xff, 0xff, 1][..]);
+    let capacity = buf.capacity();
+
+    assert_eq!(codec.decode_complete(&mut buf).unwrap(), Some(Message::from_pixels(1, &[[1, 2, 3]])));
+    assert_eq!(codec.decode_complete(&mut buf).unwrap(), None);
+    assert_eq!(buf.len(), 5);
+    assert!(buf.capacity() < 0xffff);
+    assert!(buf.capacity() <= capacity);
+
+}
+
+#[test]
+fn should_decode_frames_fed_byte_by_byte() {
+
+    let mut codec = OpcCodec::new();
+    let msgs = sample_messages();
+    let encoded = encode_all(&msgs);
+
+    let mut buf = BytesMut::new();
+    let mut received = Vec::new();
+    for byte in encoded.iter() {
+        buf.extend_from_slice(&[*byte]);
+        while let Some(msg) = codec.decode(&mut buf).unwrap() {
+            received.push(msg);
+        }
+    }
+
+    assert_eq!(msgs, received);
+    assert!(buf.is_empty());
+
+}
+
+#[test]
+fn should_decode_frames_fed_in_random_chunks() {
+
+    let mut codec = OpcCodec::new();
+    let msgs = sample_messages();
+    let encoded = encode_all(&msgs);
+
+    for _ in 0..50 {
+        let mut buf = BytesMut::new();
+        let mut received = Vec::new();
+        let mut rest = &encoded[..];
+        while !rest.is_empty() {
+            let n = std::cmp::min(rest.len(), rand::random::<usize>() % 512 + 1);
+            buf.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+            while let Some(msg) = codec.decode(&mut buf).unwrap() {
+                received.push(msg);
+            }
+        }
+
+        assert_eq!(msgs, received);
+        assert!(buf.is_empty());
+    }
+
+}
+
+#[test]
+fn should_reject_short_system_command() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::from(&[4u8, SYS_EXCLUSIVE, 0, 1, 9][..]);
+
+    match codec.decode(&mut buf) {
+        Err(OpcError::SysExTooShort) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(buf.is_empty());
+
+}
+
+#[test]
+fn should_report_unknown_command() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::from(&[4u8, 0x42, 0, 1, 9][..]);
+
+    match codec.decode(&mut buf) {
+        Err(OpcError::UnknownCommand(0x42)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(buf.is_empty());
+
+}
+
+#[test]
+fn should_report_truncated_stream() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::from(&[4u8, SET_PIXEL_COLORS, 0, 6, 1, 2, 3][..]);
+
+    match codec.decode_eof(&mut buf) {
+        Err(OpcError::Truncated) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+}
+
+#[test]
+fn should_reject_oversized_message() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::new();
+    let msg = Message::from_pixels(1, &[[0; 3]; 25_000]);
+
+    match codec.encode(msg, &mut buf) {
+        Err(OpcError::MessageTooLarge(75_000)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(buf.is_empty());
+
+}
+
+#[test]
+fn should_split_oversized_pixel_message() {
+
+    let mut codec = OpcCodec::new().oversize_policy(OversizePolicy::Split);
+    let mut buf = BytesMut::new();
+    let pixels: Vec<[u8; 3]> = (0..25_000).map(|i| [i as u8, (i >> 8) as u8, 7]).collect();
+
+    assert!(codec.encode(Message::from_pixels(6, &pixels), &mut buf).is_ok());
+    assert_eq!(buf.len(), 8 + 75_000);
+
+    let first = codec.decode(&mut buf).unwrap().unwrap();
+    let second = codec.decode(&mut buf).unwrap().unwrap();
+
+    assert_eq!(first, Message::from_pixels(6, &pixels[..MAX_PIXELS_PER_MESSAGE]));
+    assert_eq!(second, Message::from_pixels(6, &pixels[MAX_PIXELS_PER_MESSAGE..]));
+    assert!(buf.is_empty());
+
+}
+
+#[test]
+fn should_not_split_oversized_system_command() {
+
+    let mut codec = OpcCodec::new().oversize_policy(OversizePolicy::Split);
+    let mut buf = BytesMut::new();
+    let msg = Message::from_data(1, &[0, 1], &vec![0; MAX_MESSAGE_SIZE]);
+
+    match codec.encode(msg, &mut buf) {
+        Err(OpcError::MessageTooLarge(len)) => assert_eq!(len, MAX_MESSAGE_SIZE + 2),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(buf.is_empty());
+
+}
+
+#[test]
+fn should_pass_through_unknown_command() {
+
+    let mut codec = OpcCodec::new().unknown_command_policy(UnknownCommandPolicy::PassThrough);
+    let mut buf = BytesMut::new();
+    let test_msg = Message {
+        channel: 3,
+        command: Command::Unknown {
+            command: 0x42,
+            data: vec![1, 2, 3, 4],
+        },
+    };
+
+    assert!(codec.encode(test_msg.clone(), &mut buf).is_ok());
+    assert_eq!(&buf[..4], &[3, 0x42, 0, 4]);
+
+    let recv_msg = codec.decode(&mut buf).unwrap().unwrap();
+
+    assert_eq!(test_msg, recv_msg);
+
+}
+
+#[test]
+fn should_skip_unknown_command() {
+
+    let mut codec = OpcCodec::new().unknown_command_policy(UnknownCommandPolicy::Skip);
+    let mut buf = BytesMut::from(&[3u8, 0x42, 0, 2, 1, 2, 3, 0x43, 0, 0][..]);
+    let pixel_msg = Message::from_pixels(5, &[[1, 2, 3]]);
+    codec.encode(pixel_msg.clone(), &mut buf).unwrap();
+
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some(pixel_msg));
+    assert!(buf.is_empty());
+
+}
+
+#[test]
+fn should_decode_pixels_without_copying() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = encode_all(&[Message::from_pixels(1, &[[1, 2, 3]; 100])]);
+    let range = buf.as_ptr_range();
+
+    let msg = codec.decode(&mut buf).unwrap().unwrap();
+
+    match msg.command {
+        Command::SetPixelColors { ref pixels } => assert!(range.contains(&pixels.as_bytes().as_ptr())),
+        ref other => panic!("unexpected command: {:?}", other),
+    }
+
+}
+
+#[test]
+fn should_keep_trailing_partial_pixel() {
+
+    let mut codec = OpcCodec::new();
+    let mut buf = BytesMut::from(&[1u8, SET_PIXEL_COLORS, 0, 4, 1, 2, 3, 4][..]);
+
+    let msg = codec.decode(&mut buf).unwrap().unwrap();
+
+    assert_eq!(msg.len(), 4);
+    assert_eq!(&msg.command, &Command::SetPixelColors { pixels: crate::Pixels::from_bytes(vec![1, 2, 3, 4].into()) });
+    assert_ne!(msg, Message::from_pixels(1, &[[1, 2, 3]]));
+    codec.encode(msg, &mut buf).unwrap();
+    assert_eq!(&buf[..], &[1, SET_PIXEL_COLORS, 0, 4, 1, 2, 3, 4]);
+
+}
+
+#[test]
+fn should_encode_borrowed_message() {
+
+    let mut codec = OpcCodec::new();
+    let pixels = [[1, 2, 3]; 10];
+    let mut owned = BytesMut::new();
+    let mut borrowed = BytesMut::new();
+
+    codec.encode(Message::from_pixels(2, &pixels), &mut owned).unwrap();
+    codec.encode(MessageRef::from_pixels(2, &pixels), &mut borrowed).unwrap();
+
+    assert_eq!(owned, borrowed);
+    assert_eq!(codec.decode(&mut borrowed).unwrap().unwrap(), Message::from_pixels(2, &pixels));
+
+}
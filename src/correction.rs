@@ -0,0 +1,265 @@
+//! Color correction lookup tables, for controllers that do no correction of their own.
+//!
+//! A `Curve` describes the same correction Fadecandy applies, plus a global brightness,
+//! and builds per-component tables that correct pixels right before they are encoded:
+//!
+//! ```rust
+//! use opc::correction::Curve;
+//!
+//! let lut = Curve::new().gamma(2.5).whitepoint([1.0, 0.9, 0.8]).brightness(0.5).lut8();
+//! let mut pixels = vec![[255u8, 128, 0]; 100];
+//!
+//! lut.apply(&mut pixels);
+//! assert_eq!(pixels[0], [128, 17, 0]);
+//! ```
+
+use crate::{Command, Message, Pixels};
+
+/// Describes the correction applied to every color component.
+///
+/// Like Fadecandy, a component from 0 to 1 is scaled by its whitepoint first, giving `x`.
+/// While `x * linear_slope` stays at or below `linear_cutoff` it is the output,
+/// above that the power section takes over where the linear section leaves off:
+/// `linear_cutoff + ((x - linear_slope * linear_cutoff) / (1 - linear_cutoff)) ^ gamma * (1 - linear_cutoff)`.
+/// The result is scaled by the brightness.
+#[derive (Clone, Copy, Debug, PartialEq)]
+pub struct Curve {
+    gamma: f64,
+    whitepoint: [f64; 3],
+    brightness: f64,
+    linear_slope: f64,
+    linear_cutoff: f64,
+}
+
+impl Curve {
+    /// Create new Curve that leaves colors unchanged
+    pub fn new() -> Curve {
+        Curve {
+            gamma: 1.0,
+            whitepoint: [1.0, 1.0, 1.0],
+            brightness: 1.0,
+            linear_slope: 1.0,
+            linear_cutoff: 0.0,
+        }
+    }
+
+    /// Set the exponent applied to every component
+    pub fn gamma(mut self, gamma: f64) -> Curve {
+        self.gamma = gamma;
+        self
+    }
+
+    /// Set the scale of the red, green and blue components, applied before the curve
+    pub fn whitepoint(mut self, whitepoint: [f64; 3]) -> Curve {
+        self.whitepoint = whitepoint;
+        self
+    }
+
+    /// Set the scale of every component after the curve, from 0 to 1
+    pub fn brightness(mut self, brightness: f64) -> Curve {
+        self.brightness = brightness;
+        self
+    }
+
+    /// Use a linear section of `slope` for outputs up to `cutoff`, avoiding the flat start of a steep gamma
+    pub fn linear(mut self, slope: f64, cutoff: f64) -> Curve {
+        self.linear_slope = slope;
+        self.linear_cutoff = cutoff;
+        self
+    }
+
+    /// Corrected value of a component, from 0 to 1
+    pub fn eval(&self, component: usize, x: f64) -> f64 {
+        let x = x * self.whitepoint[component];
+        let y = if x * self.linear_slope <= self.linear_cutoff {
+            x * self.linear_slope
+        } else {
+            // Fadecandy's offset goes below zero for slopes above 1, stay flat there instead of going negative
+            let x = (x - self.linear_slope * self.linear_cutoff).max(0.0);
+            let scale = 1.0 - self.linear_cutoff;
+            self.linear_cutoff + (x / scale).powf(self.gamma) * scale
+        };
+        (y.clamp(0.0, 1.0) * self.brightness).clamp(0.0, 1.0)
+    }
+
+    /// Build a table correcting 8-bit components to 8 bits
+    pub fn lut8(&self) -> Lut8 {
+        Lut8 { tables: self.tables(|y| (y * 255.0).round() as u8) }
+    }
+
+    /// Build a table correcting 8-bit components to 16 bits, keeping the precision of dark colors
+    pub fn lut16(&self) -> Lut16 {
+        Lut16 { tables: Box::new(self.tables(|y| (y * 65535.0).round() as u16)) }
+    }
+
+    fn tables<T: Copy + Default>(&self, quantize: impl Fn(f64) -> T) -> [[T; 256]; 3] {
+        let mut tables = [[T::default(); 256]; 3];
+        for (component, table) in tables.iter_mut().enumerate() {
+            for (input, output) in table.iter_mut().enumerate() {
+                *output = quantize(self.eval(component, input as f64 / 255.0));
+            }
+        }
+        tables
+    }
+}
+
+impl Default for Curve {
+    fn default() -> Curve {
+        Curve::new()
+    }
+}
+
+#[cfg(feature = "fadecandy")]
+impl<'a> From<&'a crate::fadecandy::ColorCorrection> for Curve {
+    fn from(correction: &'a crate::fadecandy::ColorCorrection) -> Curve {
+        Curve::new()
+            .gamma(correction.gamma)
+            .whitepoint(correction.whitepoint)
+            .linear(correction.linear_slope, correction.linear_cutoff)
+    }
+}
+
+/// Lookup table correcting 8-bit components to 8 bits.
+#[derive (Clone, Debug, PartialEq, Eq)]
+pub struct Lut8 {
+    tables: [[u8; 256]; 3],
+}
+
+impl Lut8 {
+    /// Correct a single pixel
+    pub fn correct(&self, pixel: [u8; 3]) -> [u8; 3] {
+        [self.tables[0][pixel[0] as usize], self.tables[1][pixel[1] as usize], self.tables[2][pixel[2] as usize]]
+    }
+
+    /// Correct pixels in place
+    pub fn apply(&self, pixels: &mut [[u8; 3]]) {
+        for pixel in pixels {
+            *pixel = self.correct(*pixel);
+        }
+    }
+
+    /// Correct raw pixel data in place, leaving trailing bytes that do not fill a pixel untouched
+    pub fn apply_data(&self, data: &mut [u8]) {
+        self.apply(data.as_chunks_mut().0);
+    }
+
+    /// Correct the pixels of a Set Pixel Colors message, leaving other messages unchanged
+    pub fn apply_message(&self, msg: Message) -> Message {
+        match msg.command {
+            Command::SetPixelColors { pixels } => {
+                let mut data = pixels.as_bytes().to_vec();
+                self.apply_data(&mut data);
+                Message {
+                    channel: msg.channel,
+                    command: Command::SetPixelColors { pixels: Pixels::from_bytes(data.into()) },
+                }
+            }
+            _ => msg,
+        }
+    }
+}
+
+/// Lookup table correcting 8-bit components to 16 bits, to be sent as `format::Rgb16`.
+#[derive (Clone, Debug, PartialEq, Eq)]
+pub struct Lut16 {
+    tables: Box<[[u16; 256]; 3]>,
+}
+
+impl Lut16 {
+    /// Correct a single pixel
+    pub fn correct(&self, pixel: [u8; 3]) -> [u16; 3] {
+        [self.tables[0][pixel[0] as usize], self.tables[1][pixel[1] as usize], self.tables[2][pixel[2] as usize]]
+    }
+
+    /// Correct pixels into a new buffer
+    pub fn apply(&self, pixels: &[[u8; 3]]) -> Vec<[u16; 3]> {
+        pixels.iter().map(|&pixel| self.correct(pixel)).collect()
+    }
+}
+
+#[test]
+fn should_build_identity_tables() {
+
+    let lut8 = Curve::new().lut8();
+    let lut16 = Curve::new().lut16();
+
+    for i in 0..=255 {
+        assert_eq!(lut8.correct([i, i, i]), [i, i, i]);
+        assert_eq!(lut16.correct([i, i, i]), [i as u16 * 257; 3]);
+    }
+
+}
+
+#[test]
+fn should_apply_gamma_whitepoint_and_brightness() {
+
+    let lut = Curve::new().gamma(2.0).whitepoint([1.0, 0.5, 0.0]).brightness(0.5).lut8();
+    let mut data = vec![255, 255, 255, 128, 128, 128, 7];
+
+    lut.apply_data(&mut data);
+
+    assert_eq!(data, vec![128, 32, 0, 32, 8, 0, 7]);
+
+}
+
+#[test]
+fn should_use_linear_section_below_cutoff() {
+
+    let curve = Curve::new().gamma(3.0).linear(0.5, 0.1);
+
+    assert_eq!(curve.eval(0, 0.1), 0.05);
+    assert!((curve.eval(0, 0.5) - 0.2125).abs() < 1e-12);
+    assert_eq!(curve.lut16().correct([255, 0, 0]), [65535, 0, 0]);
+
+}
+
+#[test]
+fn should_build_non_decreasing_tables_with_linear_section() {
+
+    for &gamma in &[1.0, 2.0, 2.5, 3.0] {
+        for &slope in &[0.25, 0.5, 1.0, 2.0, 4.0] {
+            for &cutoff in &[1.0 / 256.0, 0.01, 0.1, 0.5] {
+                let curve = Curve::new().gamma(gamma).whitepoint([1.0, 0.9, 0.5]).linear(slope, cutoff);
+                let lut8 = curve.lut8();
+                let lut16 = curve.lut16();
+                for i in 1..=255 {
+                    let (lower8, upper8) = (lut8.correct([i - 1; 3]), lut8.correct([i; 3]));
+                    let (lower16, upper16) = (lut16.correct([i - 1; 3]), lut16.correct([i; 3]));
+                    for component in 0..3 {
+                        assert!(upper8[component] >= lower8[component], "lut8 decreases at {} for {:?}", i, curve);
+                        assert!(upper16[component] >= lower16[component], "lut16 decreases at {} for {:?}", i, curve);
+                    }
+                }
+            }
+        }
+    }
+
+}
+
+#[cfg(feature = "fadecandy")]
+#[test]
+fn should_match_fadecandy_color_correction() {
+    use crate::fadecandy::ColorCorrection;
+
+    let correction = ColorCorrection {
+        gamma: 2.5,
+        whitepoint: [0.98, 1.0, 1.0],
+        linear_slope: 0.5,
+        linear_cutoff: 0.01,
+    };
+    let lut = Curve::from(&correction).lut16();
+
+    // The curve fcserver computes for its lookup tables
+    for i in 0..=255u8 {
+        let input = correction.whitepoint[0] * i as f64 / 255.0;
+        let output = if input * correction.linear_slope <= correction.linear_cutoff {
+            input * correction.linear_slope
+        } else {
+            let input = input - correction.linear_slope * correction.linear_cutoff;
+            let scale = 1.0 - correction.linear_cutoff;
+            correction.linear_cutoff + (input / scale).powf(correction.gamma) * scale
+        };
+        assert_eq!(lut.correct([i, 0, 0])[0], (output.clamp(0.0, 1.0) * 65535.0).round() as u16);
+    }
+
+}
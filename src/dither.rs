@@ -0,0 +1,226 @@
+//! Temporal dithering of high bit depth frames down to 8-bit pixels.
+//!
+//! Rounding every frame to 8 bits makes slow, dark fades step visibly.
+//! `Dither` keeps the rounding error of every component and carries it into the next frame,
+//! so over a few frames the emitted values average out to the rendered ones.
+//! `Dithered` runs that at a fixed output rate in front of a `Client`:
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "tokio")]
+//! # mod example {
+//! use std::time::Duration;
+//!
+//! use opc::dither::Dithered;
+//! use opc::Client;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let output = Dithered::spawn(Client::connect("192.168.1.230:7890"), Duration::from_millis(5));
+//!     let mut level = 0.0;
+//!
+//!     loop {
+//!         level = (level + 0.0005) % 0.05;
+//!         output.set_pixels(1, &vec![[level; 3]; 64]);
+//!         tokio::time::sleep(Duration::from_millis(16)).await;
+//!     }
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+
+/// Per-pixel error accumulators for dithering successive frames of one channel.
+#[derive (Clone, Debug, Default)]
+pub struct Dither {
+    error: Vec<[f32; 3]>,
+}
+
+impl Dither {
+    /// Create new Dither with no accumulated error
+    pub fn new() -> Dither {
+        Dither { error: Vec::new() }
+    }
+
+    /// Quantize a frame of components from 0 to 1, carrying the rounding error into the next frame
+    pub fn quantize(&mut self, frame: &[[f32; 3]]) -> Vec<[u8; 3]> {
+        self.error.resize(frame.len(), [0.0; 3]);
+        frame.iter()
+            .zip(self.error.iter_mut())
+            .map(|(pixel, error)| {
+                let mut out = [0; 3];
+                for i in 0..3 {
+                    let value = pixel[i].clamp(0.0, 1.0) * 255.0 + error[i];
+                    let quantized = value.round().clamp(0.0, 255.0);
+                    error[i] = value - quantized;
+                    out[i] = quantized as u8;
+                }
+                out
+            })
+            .collect()
+    }
+
+    /// Forget the accumulated error
+    pub fn reset(&mut self) {
+        self.error.clear();
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub use self::stage::Dithered;
+
+#[cfg(feature = "tokio")]
+mod stage {
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use tokio::task::JoinHandle;
+    use tokio::time::MissedTickBehavior;
+
+    use super::Dither;
+    use crate::Client;
+
+    /// Latest frame of a channel and its accumulated error
+    #[derive (Debug, Default)]
+    struct Channel {
+        frame: Vec<[f32; 3]>,
+        dither: Dither,
+    }
+
+    /// Dithers the latest frame of every channel to a `Client` at a fixed output rate.
+    ///
+    /// Frames are re-sent on every tick even when they do not change, which is what spreads the error over time,
+    /// so the output rate should be well above the render rate. A frame the client rejects is skipped on that tick
+    /// and retried on the next. The task stops once this is dropped or the client's task stops.
+    #[derive (Debug)]
+    pub struct Dithered {
+        channels: Arc<Mutex<BTreeMap<u8, Channel>>>,
+        client: Client,
+        task: JoinHandle<()>,
+    }
+
+    impl Dithered {
+        /// Spawn the output task on the current tokio runtime, sending a frame every `interval`
+        pub fn spawn(client: Client, interval: Duration) -> Dithered {
+            let channels = Arc::new(Mutex::new(BTreeMap::new()));
+            let task = tokio::spawn(run(Arc::downgrade(&channels), client.clone(), interval));
+            Dithered { channels, client, task }
+        }
+
+        /// Replace the frame of a channel, with components from 0 to 1
+        pub fn set_pixels(&self, channel: u8, pixels: &[[f32; 3]]) {
+            let mut channels = self.channels.lock().unwrap();
+            let state: &mut Channel = channels.entry(channel).or_default();
+            state.frame.clear();
+            state.frame.extend_from_slice(pixels);
+        }
+
+        /// The client frames are sent through
+        pub fn client(&self) -> &Client {
+            &self.client
+        }
+
+        /// Whether the output task is still sending frames
+        pub fn is_running(&self) -> bool {
+            !self.task.is_finished()
+        }
+    }
+
+    async fn run(channels: std::sync::Weak<Mutex<BTreeMap<u8, Channel>>>, client: Client, interval: Duration) {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let channels = match channels.upgrade() {
+                Some(channels) => channels,
+                None => return,
+            };
+            for (&channel, state) in channels.lock().unwrap().iter_mut() {
+                if client.set_pixels(channel, &state.dither.quantize(&state.frame)).is_err() && client.is_stopped() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn should_average_out_to_rendered_value() {
+
+    let mut dither = Dither::new();
+    let frame = [[10.25 / 255.0, 0.5 / 255.0, 1.0]];
+
+    let frames: Vec<_> = (0..4).map(|_| dither.quantize(&frame)[0]).collect();
+
+    assert_eq!(frames.iter().map(|p| p[0] as u32).sum::<u32>(), 41);
+    assert_eq!(frames.iter().map(|p| p[1] as u32).sum::<u32>(), 2);
+    assert!(frames.iter().all(|p| p[2] == 255));
+
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn should_send_dithered_frames_at_output_rate() {
+    use futures_util::StreamExt;
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    use crate::{Command, OpcCodec};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = crate::Client::connect(listener.local_addr().unwrap().to_string());
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut server = Framed::new(socket, OpcCodec::new());
+
+    let output = Dithered::spawn(client, std::time::Duration::from_millis(10));
+    output.set_pixels(1, &[[0.5 / 255.0; 3]]);
+
+    let mut levels = Vec::new();
+    while levels.len() < 4 {
+        match server.next().await.unwrap().unwrap().command {
+            Command::SetPixelColors { pixels } => levels.push(pixels[0][0]),
+            other => panic!("unexpected command: {:?}", other),
+        }
+    }
+    assert!(levels.contains(&0) && levels.contains(&1));
+
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn should_keep_sending_other_channels_after_a_rejected_frame() {
+    use futures_util::StreamExt;
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    use crate::OpcCodec;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = crate::Client::connect(listener.local_addr().unwrap().to_string());
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut server = Framed::new(socket, OpcCodec::new());
+
+    let output = Dithered::spawn(client, std::time::Duration::from_millis(10));
+    output.set_pixels(1, &vec![[0.0; 3]; 30_000]);
+    output.set_pixels(2, &[[1.0; 3]]);
+
+    for _ in 0..3 {
+        assert_eq!(server.next().await.unwrap().unwrap().channel, 2);
+    }
+    assert!(output.is_running());
+
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn should_stop_once_the_client_stops() {
+
+    let output = Dithered::spawn(crate::Client::connect("bogus://nowhere"), std::time::Duration::from_millis(10));
+    output.set_pixels(1, &[[1.0; 3]]);
+
+    tokio::time::timeout(std::time::Duration::from_secs(1), async {
+        while output.is_running() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }).await.unwrap();
+
+}
@@ -0,0 +1,66 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Describes why an OPC message could not be encoded or decoded.
+///
+/// New variants may be added, so matches need a wildcard arm.
+#[derive (Debug)]
+#[non_exhaustive]
+pub enum OpcError {
+    /// The command byte is neither Set Pixel Colors nor System Exclusive.
+    UnknownCommand(u8),
+    /// The message data is longer than the 65535 bytes a frame can carry.
+    MessageTooLarge(usize),
+    /// The stream ended in the middle of a frame.
+    Truncated,
+    /// A System Exclusive message is too short to hold its two-byte system ID.
+    SysExTooShort,
+    /// A System Exclusive message does not hold the payload it was parsed as.
+    InvalidSysEx(String),
+    /// An encoded frame of this many bytes does not fit in a single datagram.
+    DatagramTooLarge(usize),
+    /// The underlying transport failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for OpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpcError::UnknownCommand(command) => write!(f, "unknown OPC command 0x{:02x}", command),
+            OpcError::MessageTooLarge(len) => {
+                write!(f, "OPC message data of {} bytes exceeds the 65535 byte limit", len)
+            }
+            OpcError::Truncated => write!(f, "stream ended in the middle of an OPC frame"),
+            OpcError::SysExTooShort => write!(f, "OPC system exclusive message is missing its system ID"),
+            OpcError::InvalidSysEx(ref reason) => write!(f, "invalid OPC system exclusive message: {}", reason),
+            OpcError::DatagramTooLarge(len) => write!(f, "OPC frame of {} bytes does not fit in a datagram", len),
+            OpcError::Io(ref err) => write!(f, "OPC transport error: {}", err),
+        }
+    }
+}
+
+impl Error for OpcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            OpcError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for OpcError {
+    fn from(err: io::Error) -> OpcError {
+        OpcError::Io(err)
+    }
+}
+
+impl From<OpcError> for io::Error {
+    fn from(err: OpcError) -> io::Error {
+        match err {
+            OpcError::Io(err) => err,
+            OpcError::Truncated => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Typed System Exclusive messages understood by [Fadecandy](https://github.com/scanlime/fadecandy).
+//!
+//! Fadecandy messages use system ID 0x0001, followed by a two-byte command ID and the command's data.
+//!
+//! ```rust
+//! use opc::fadecandy::{ColorCorrection, FirmwareConfig};
+//!
+//! let correction = ColorCorrection { gamma: 2.5, ..ColorCorrection::default() }.to_message(0);
+//! let config = FirmwareConfig { disable_dithering: true, ..FirmwareConfig::default() }.to_message(0);
+//!
+//! assert_eq!(ColorCorrection::from_message(&correction).unwrap().gamma, 2.5);
+//! assert!(FirmwareConfig::from_message(&config).unwrap().disable_dithering);
+//! ```
+//!
+//! `SysEx` covers every Fadecandy command, for use with a `SysExRegistry`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::sysex::SysExPayload;
+use crate::{Command, Message, OpcError};
+
+/// Fadecandy System ID
+pub const SYSTEM_ID: [u8; 2] = [0x00, 0x01];
+
+const SET_COLOR_CORRECTION: [u8; 2] = [0x00, 0x01];
+const SET_FIRMWARE_CONFIG: [u8; 2] = [0x00, 0x02];
+
+const DISABLE_DITHERING: u8 = 1 << 0;
+const DISABLE_INTERPOLATION: u8 = 1 << 1;
+const MANUAL_LED: u8 = 1 << 2;
+const LED_ON: u8 = 1 << 3;
+
+/// Global color correction, sent to the server as a JSON object.
+///
+/// Missing keys take their default values when parsed.
+#[derive (Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ColorCorrection {
+    /// Exponent applied to every color component.
+    pub gamma: f64,
+    /// Scale of the red, green and blue components, applied before the curve.
+    pub whitepoint: [f64; 3],
+    /// Slope of the linear section used for dark components.
+    pub linear_slope: f64,
+    /// Output value, from 0 to 1, up to which the linear section is used, and where the gamma section starts.
+    pub linear_cutoff: f64,
+}
+
+impl Default for ColorCorrection {
+    fn default() -> ColorCorrection {
+        ColorCorrection {
+            gamma: 1.0,
+            whitepoint: [1.0, 1.0, 1.0],
+            linear_slope: 1.0,
+            linear_cutoff: 0.0,
+        }
+    }
+}
+
+impl ColorCorrection {
+    /// Encode as the data of a Fadecandy System Exclusive message, following the system ID
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = SET_COLOR_CORRECTION.to_vec();
+        serde_json::to_writer(&mut data, self).expect("color correction serializes to JSON");
+        data
+    }
+
+    /// Parse the data of a Fadecandy System Exclusive message, following the system ID
+    pub fn decode(data: &[u8]) -> Result<ColorCorrection, OpcError> {
+        let json = command_data(data, SET_COLOR_CORRECTION)?;
+        serde_json::from_slice(json).map_err(|err| OpcError::InvalidSysEx(err.to_string()))
+    }
+
+    /// Create new Message setting the color correction of the devices on `channel`
+    pub fn to_message(&self, channel: u8) -> Message {
+        Message::from_data(channel, &SYSTEM_ID, &self.encode())
+    }
+
+    /// Parse a Fadecandy System Exclusive message
+    pub fn from_message(msg: &Message) -> Result<ColorCorrection, OpcError> {
+        ColorCorrection::decode(fadecandy_data(msg)?)
+    }
+}
+
+/// Firmware configuration flags.
+#[derive (Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FirmwareConfig {
+    /// Turn off temporal dithering.
+    pub disable_dithering: bool,
+    /// Turn off interpolation between keyframes.
+    pub disable_interpolation: bool,
+    /// Control the status LED with `led_on` instead of showing USB activity.
+    pub manual_led: bool,
+    /// State of the status LED under manual control.
+    pub led_on: bool,
+}
+
+impl FirmwareConfig {
+    /// Encode as the data of a Fadecandy System Exclusive message, following the system ID
+    pub fn encode(&self) -> Vec<u8> {
+        let mut flags = 0;
+        for &(set, flag) in &[(self.disable_dithering, DISABLE_DITHERING),
+                              (self.disable_interpolation, DISABLE_INTERPOLATION),
+                              (self.manual_led, MANUAL_LED),
+                              (self.led_on, LED_ON)] {
+            if set {
+                flags |= flag;
+            }
+        }
+        vec![SET_FIRMWARE_CONFIG[0], SET_FIRMWARE_CONFIG[1], flags]
+    }
+
+    /// Parse the data of a Fadecandy System Exclusive message, following the system ID
+    ///
+    /// Reserved bits and bytes are ignored.
+    pub fn decode(data: &[u8]) -> Result<FirmwareConfig, OpcError> {
+        let flags = match command_data(data, SET_FIRMWARE_CONFIG)?.first() {
+            Some(&flags) => flags,
+            None => return Err(OpcError::InvalidSysEx("firmware configuration is empty".to_string())),
+        };
+        Ok(FirmwareConfig {
+            disable_dithering: flags & DISABLE_DITHERING != 0,
+            disable_interpolation: flags & DISABLE_INTERPOLATION != 0,
+            manual_led: flags & MANUAL_LED != 0,
+            led_on: flags & LED_ON != 0,
+        })
+    }
+
+    /// Create new Message configuring the firmware of the devices on `channel`
+    pub fn to_message(&self, channel: u8) -> Message {
+        Message::from_data(channel, &SYSTEM_ID, &self.encode())
+    }
+
+    /// Parse a Fadecandy System Exclusive message
+    pub fn from_message(msg: &Message) -> Result<FirmwareConfig, OpcError> {
+        FirmwareConfig::decode(fadecandy_data(msg)?)
+    }
+}
+
+/// Any Fadecandy System Exclusive message.
+#[derive (Clone, Debug, PartialEq)]
+pub enum SysEx {
+    /// Set the global color correction.
+    ColorCorrection(ColorCorrection),
+    /// Set the firmware configuration.
+    FirmwareConfig(FirmwareConfig),
+}
+
+impl SysExPayload for SysEx {
+    const SYSTEM_ID: [u8; 2] = SYSTEM_ID;
+
+    fn encode(&self) -> Vec<u8> {
+        match *self {
+            SysEx::ColorCorrection(ref correction) => correction.encode(),
+            SysEx::FirmwareConfig(ref config) => config.encode(),
+        }
+    }
+
+    fn decode(data: &[u8]) -> Result<SysEx, OpcError> {
+        match data.first_chunk::<2>() {
+            Some(&SET_FIRMWARE_CONFIG) => FirmwareConfig::decode(data).map(SysEx::FirmwareConfig),
+            _ => ColorCorrection::decode(data).map(SysEx::ColorCorrection),
+        }
+    }
+}
+
+impl From<ColorCorrection> for SysEx {
+    fn from(correction: ColorCorrection) -> SysEx {
+        SysEx::ColorCorrection(correction)
+    }
+}
+
+impl From<FirmwareConfig> for SysEx {
+    fn from(config: FirmwareConfig) -> SysEx {
+        SysEx::FirmwareConfig(config)
+    }
+}
+
+/// The data of a Fadecandy System Exclusive message, following the system ID
+fn fadecandy_data(msg: &Message) -> Result<&[u8], OpcError> {
+    match msg.command {
+        Command::SystemExclusive { id: SYSTEM_ID, ref data } => Ok(data),
+        _ => Err(OpcError::InvalidSysEx("not a Fadecandy system exclusive message".to_string())),
+    }
+}
+
+/// The data following an expected command ID
+fn command_data(data: &[u8], command: [u8; 2]) -> Result<&[u8], OpcError> {
+    match data.split_first_chunk::<2>() {
+        Some((id, rest)) if *id == command => Ok(rest),
+        Some((id, _)) => Err(OpcError::InvalidSysEx(format!("unexpected Fadecandy command {:02x}{:02x}", id[0], id[1]))),
+        None => Err(OpcError::InvalidSysEx("missing Fadecandy command".to_string())),
+    }
+}
+
+#[test]
+fn should_encode_color_correction_as_json() {
+
+    let correction = ColorCorrection {
+        gamma: 2.5,
+        whitepoint: [0.5, 0.75, 1.0],
+        linear_slope: 1.0,
+        linear_cutoff: 0.25,
+    };
+    let msg = correction.to_message(0);
+
+    match msg.command {
+        Command::SystemExclusive { id, ref data } => {
+            assert_eq!(id, [0x00, 0x01]);
+            assert_eq!(&data[..2], &[0x00, 0x01]);
+            assert_eq!(std::str::from_utf8(&data[2..]).unwrap(),
+                       r#"{"gamma":2.5,"whitepoint":[0.5,0.75,1.0],"linearSlope":1.0,"linearCutoff":0.25}"#);
+        }
+        ref other => panic!("unexpected command: {:?}", other),
+    }
+    assert_eq!(ColorCorrection::from_message(&msg).unwrap(), correction);
+
+}
+
+#[test]
+fn should_parse_partial_color_correction() {
+
+    let mut data = SET_COLOR_CORRECTION.to_vec();
+    data.extend_from_slice(br#"{ "gamma": 2.2 }"#);
+
+    let correction = ColorCorrection::decode(&data).unwrap();
+
+    assert_eq!(correction, ColorCorrection { gamma: 2.2, ..ColorCorrection::default() });
+
+}
+
+#[test]
+fn should_roundtrip_firmware_config() {
+
+    let config = FirmwareConfig {
+        disable_dithering: true,
+        disable_interpolation: false,
+        manual_led: true,
+        led_on: true,
+    };
+    let msg = config.to_message(0);
+
+    assert_eq!(msg, Message::from_data(0, &SYSTEM_ID, &[0x00, 0x02, 0b1101]));
+    assert_eq!(FirmwareConfig::from_message(&msg).unwrap(), config);
+
+}
+
+#[test]
+fn should_reject_other_messages() {
+
+    let config = FirmwareConfig::default().to_message(0);
+
+    assert!(ColorCorrection::from_message(&config).is_err());
+    assert!(FirmwareConfig::from_message(&Message::from_data(0, &[0x00, 0x02], &[0x00, 0x02, 0])).is_err());
+    assert!(FirmwareConfig::from_message(&Message::from_pixels(0, &[[0; 3]])).is_err());
+
+}
+
+#[test]
+fn should_decode_any_command() {
+
+    let config = FirmwareConfig { led_on: true, ..FirmwareConfig::default() };
+    let correction = ColorCorrection { gamma: 2.5, ..ColorCorrection::default() };
+
+    assert_eq!(SysEx::from_message(&config.to_message(0)).unwrap(), SysEx::FirmwareConfig(config));
+    assert_eq!(SysEx::from_message(&correction.to_message(0)).unwrap(), SysEx::ColorCorrection(correction.clone()));
+    assert_eq!(SysEx::from(correction.clone()).to_message(1), correction.to_message(1));
+    assert!(SysEx::decode(&[0x00, 0x03]).is_err());
+
+}
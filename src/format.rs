@@ -0,0 +1,328 @@
+//! Pixel formats and their OPC wire representation.
+//!
+//! Set Pixel Colors carries raw bytes, which controllers interpret in their own layout.
+//! A `PixelFormat` converts between typed pixels and those bytes,
+//! so frames can be built from and decoded into whatever the hardware expects:
+//!
+//! ```rust
+//! use opc::format::{self, Rgb16, Rgbw8};
+//! use opc::Message;
+//!
+//! let msg = Message::from_format::<Rgbw8>(1, &[[255, 0, 0, 16], [0, 0, 0, 255]]);
+//! assert_eq!(msg.len(), 8);
+//!
+//! // Controllers taking 16-bit data usually expect it inside a System Exclusive message
+//! let data = format::encode::<Rgb16>(&[[0xffff, 0x8000, 0]]);
+//! let msg = Message::from_data(1, &[0x00, 0x10], &data);
+//! assert_eq!(msg.len(), 8);
+//! ```
+
+use std::fmt;
+
+use crate::{Command, CommandRef, Message, MessageRef, OpcCodec, OpcError, Pixels};
+
+/// Layout of a single pixel in the data of a message.
+pub trait PixelFormat {
+    /// The typed pixel, with its components in canonical order.
+    type Pixel: Copy;
+
+    /// Number of bytes each pixel takes up on the wire.
+    const BYTES_PER_PIXEL: usize;
+
+    /// Write a pixel to exactly `BYTES_PER_PIXEL` bytes
+    fn write(pixel: &Self::Pixel, out: &mut [u8]);
+
+    /// Read a pixel from exactly `BYTES_PER_PIXEL` bytes
+    fn read(data: &[u8]) -> Self::Pixel;
+}
+
+/// Red, green, blue with 8 bits each, the layout the OPC specification describes.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgb8;
+
+/// Red, green, blue with 8 bits each, sent in green, red, blue order as WS2811 strips expect.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Grb8;
+
+/// Red, green, blue, white with 8 bits each.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgbw8;
+
+/// Red, green, blue, white with 8 bits each, sent in green, red, blue, white order as SK6812 strips expect.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Grbw8;
+
+/// Red, green, blue with 16 big endian bits each.
+#[derive (Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgb16;
+
+impl PixelFormat for Rgb8 {
+    type Pixel = [u8; 3];
+    const BYTES_PER_PIXEL: usize = 3;
+
+    fn write(pixel: &[u8; 3], out: &mut [u8]) {
+        out.copy_from_slice(pixel);
+    }
+
+    fn read(data: &[u8]) -> [u8; 3] {
+        [data[0], data[1], data[2]]
+    }
+}
+
+impl PixelFormat for Grb8 {
+    type Pixel = [u8; 3];
+    const BYTES_PER_PIXEL: usize = 3;
+
+    fn write(&[r, g, b]: &[u8; 3], out: &mut [u8]) {
+        out.copy_from_slice(&[g, r, b]);
+    }
+
+    fn read(data: &[u8]) -> [u8; 3] {
+        [data[1], data[0], data[2]]
+    }
+}
+
+impl PixelFormat for Rgbw8 {
+    type Pixel = [u8; 4];
+    const BYTES_PER_PIXEL: usize = 4;
+
+    fn write(pixel: &[u8; 4], out: &mut [u8]) {
+        out.copy_from_slice(pixel);
+    }
+
+    fn read(data: &[u8]) -> [u8; 4] {
+        [data[0], data[1], data[2], data[3]]
+    }
+}
+
+impl PixelFormat for Grbw8 {
+    type Pixel = [u8; 4];
+    const BYTES_PER_PIXEL: usize = 4;
+
+    fn write(&[r, g, b, w]: &[u8; 4], out: &mut [u8]) {
+        out.copy_from_slice(&[g, r, b, w]);
+    }
+
+    fn read(data: &[u8]) -> [u8; 4] {
+        [data[1], data[0], data[2], data[3]]
+    }
+}
+
+impl PixelFormat for Rgb16 {
+    type Pixel = [u16; 3];
+    const BYTES_PER_PIXEL: usize = 6;
+
+    fn write(pixel: &[u16; 3], out: &mut [u8]) {
+        for (out, component) in out.chunks_exact_mut(2).zip(pixel) {
+            out.copy_from_slice(&component.to_be_bytes());
+        }
+    }
+
+    fn read(data: &[u8]) -> [u16; 3] {
+        let component = |i: usize| u16::from_be_bytes([data[2 * i], data[2 * i + 1]]);
+        [component(0), component(1), component(2)]
+    }
+}
+
+/// Order in which a strip expects the red, green and blue components of a pixel.
+#[derive (Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum ColorOrder {
+    /// Red, green, blue.
+    #[default]
+    Rgb,
+    /// Red, blue, green.
+    Rbg,
+    /// Green, red, blue.
+    Grb,
+    /// Green, blue, red.
+    Gbr,
+    /// Blue, red, green.
+    Brg,
+    /// Blue, green, red.
+    Bgr,
+}
+
+impl ColorOrder {
+    /// Index of the canonical component sent at each position
+    fn indices(self) -> [usize; 3] {
+        match self {
+            ColorOrder::Rgb => [0, 1, 2],
+            ColorOrder::Rbg => [0, 2, 1],
+            ColorOrder::Grb => [1, 0, 2],
+            ColorOrder::Gbr => [1, 2, 0],
+            ColorOrder::Brg => [2, 0, 1],
+            ColorOrder::Bgr => [2, 1, 0],
+        }
+    }
+
+    /// Reorder an RGB pixel for the strip
+    pub fn to_wire(self, pixel: [u8; 3]) -> [u8; 3] {
+        self.indices().map(|i| pixel[i])
+    }
+
+    /// Reorder a pixel from the strip into RGB
+    pub fn from_wire(self, pixel: [u8; 3]) -> [u8; 3] {
+        let mut rgb = [0; 3];
+        for (&i, component) in self.indices().iter().zip(pixel) {
+            rgb[i] = component;
+        }
+        rgb
+    }
+
+    /// Reorder raw RGB data for the strip, keeping trailing bytes that do not fill a pixel
+    pub fn to_wire_data(self, data: &[u8]) -> Vec<u8> {
+        self.remap(data, ColorOrder::to_wire)
+    }
+
+    /// Reorder raw data from the strip into RGB, keeping trailing bytes that do not fill a pixel
+    pub fn from_wire_data(self, data: &[u8]) -> Vec<u8> {
+        self.remap(data, ColorOrder::from_wire)
+    }
+
+    fn remap(self, data: &[u8], f: fn(ColorOrder, [u8; 3]) -> [u8; 3]) -> Vec<u8> {
+        let (pixels, rest) = data.as_chunks::<3>();
+        let mut out: Vec<u8> = pixels.iter().flat_map(|&pixel| f(self, pixel)).collect();
+        out.extend_from_slice(rest);
+        out
+    }
+}
+
+/// The color order of every channel, defaulting to RGB.
+///
+/// Broadcast messages on channel 0 are reordered with the order set for channel 0,
+/// so broadcasting only suits rigs whose strips share an order.
+#[derive (Clone, PartialEq, Eq)]
+pub struct ColorOrders {
+    orders: [ColorOrder; 256],
+}
+
+impl ColorOrders {
+    /// Create new Color Orders with every channel in RGB
+    pub fn new() -> ColorOrders {
+        ColorOrders { orders: [ColorOrder::Rgb; 256] }
+    }
+
+    /// Set the color order of a channel
+    pub fn channel(mut self, channel: u8, order: ColorOrder) -> ColorOrders {
+        self.orders[channel as usize] = order;
+        self
+    }
+
+    /// The color order of a channel
+    pub fn get(&self, channel: u8) -> ColorOrder {
+        self.orders[channel as usize]
+    }
+
+    /// Reorder the pixels of an RGB message for its channel's strip, before encoding
+    pub fn to_wire(&self, msg: Message) -> Message {
+        self.reorder(msg, ColorOrder::to_wire_data)
+    }
+
+    /// Reorder the pixels of a decoded message from its channel's strip into RGB
+    pub fn from_wire(&self, msg: Message) -> Message {
+        self.reorder(msg, ColorOrder::from_wire_data)
+    }
+
+    /// Encode a message with `codec`, reordering its pixels for the channel's strip first
+    pub(crate) fn encode_into(&self, codec: &OpcCodec, msg: MessageRef, dst: &mut Vec<u8>) -> Result<(), OpcError> {
+        match (msg.command, self.get(msg.channel)) {
+            (CommandRef::SetPixelColors { data }, order) if order != ColorOrder::Rgb => {
+                let data = order.to_wire_data(data);
+                let msg = MessageRef { channel: msg.channel, command: CommandRef::SetPixelColors { data: &data } };
+                codec.encode_into(msg, dst)
+            }
+            _ => codec.encode_into(msg, dst),
+        }
+    }
+
+    fn reorder(&self, msg: Message, f: fn(ColorOrder, &[u8]) -> Vec<u8>) -> Message {
+        let order = self.get(msg.channel);
+        match msg.command {
+            Command::SetPixelColors { ref pixels } if order != ColorOrder::Rgb => {
+                Message {
+                    channel: msg.channel,
+                    command: Command::SetPixelColors { pixels: Pixels::from_bytes(f(order, pixels.as_bytes()).into()) },
+                }
+            }
+            _ => msg,
+        }
+    }
+}
+
+impl Default for ColorOrders {
+    fn default() -> ColorOrders {
+        ColorOrders::new()
+    }
+}
+
+impl fmt::Debug for ColorOrders {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reordered = (0..=255u8).map(|ch| (ch, self.get(ch))).filter(|&(_, order)| order != ColorOrder::Rgb);
+        f.debug_map().entries(reordered).finish()
+    }
+}
+
+/// Encode pixels as raw message data
+pub fn encode<F: PixelFormat>(pixels: &[F::Pixel]) -> Vec<u8> {
+    let mut data = vec![0; pixels.len() * F::BYTES_PER_PIXEL];
+    for (out, pixel) in data.chunks_exact_mut(F::BYTES_PER_PIXEL).zip(pixels) {
+        F::write(pixel, out);
+    }
+    data
+}
+
+/// Decode raw message data, dropping trailing bytes that do not fill a pixel
+pub fn decode<F: PixelFormat>(data: &[u8]) -> Vec<F::Pixel> {
+    data.chunks_exact(F::BYTES_PER_PIXEL).map(F::read).collect()
+}
+
+#[test]
+fn should_swap_color_order() {
+
+    let pixels = [[1, 2, 3], [4, 5, 6]];
+
+    assert_eq!(encode::<Grb8>(&pixels), vec![2, 1, 3, 5, 4, 6]);
+    assert_eq!(decode::<Grb8>(&encode::<Grb8>(&pixels)), pixels);
+    assert_eq!(encode::<Grbw8>(&[[1, 2, 3, 4]]), vec![2, 1, 3, 4]);
+    assert_eq!(decode::<Grbw8>(&[2, 1, 3, 4]), vec![[1, 2, 3, 4]]);
+
+}
+
+#[test]
+fn should_roundtrip_wide_formats() {
+
+    let rgbw = [[1, 2, 3, 4], [5, 6, 7, 8], [9, 10, 11, 12]];
+    let rgb16 = [[0x0102, 0x0304, 0x0506]];
+
+    assert_eq!(decode::<Rgbw8>(&encode::<Rgbw8>(&rgbw)), rgbw);
+    assert_eq!(encode::<Rgb16>(&rgb16), vec![1, 2, 3, 4, 5, 6]);
+    assert_eq!(decode::<Rgb16>(&[1, 2, 3, 4, 5, 6, 7]), rgb16);
+
+}
+
+#[test]
+fn should_reorder_components() {
+
+    let orders = [ColorOrder::Rgb, ColorOrder::Rbg, ColorOrder::Grb, ColorOrder::Gbr, ColorOrder::Brg, ColorOrder::Bgr];
+
+    assert_eq!(ColorOrder::Grb.to_wire([1, 2, 3]), [2, 1, 3]);
+    assert_eq!(ColorOrder::Brg.to_wire([1, 2, 3]), [3, 1, 2]);
+    for &order in &orders {
+        assert_eq!(order.from_wire(order.to_wire([1, 2, 3])), [1, 2, 3]);
+    }
+    assert_eq!(ColorOrder::Brg.to_wire_data(&[1, 2, 3, 4]), vec![3, 1, 2, 4]);
+
+}
+
+#[test]
+fn should_reorder_configured_channels() {
+
+    let orders = ColorOrders::new().channel(2, ColorOrder::Grb);
+    let msg = Message::from_pixels(2, &[[1, 2, 3]]);
+
+    assert_eq!(orders.to_wire(msg.clone()), Message::from_pixels(2, &[[2, 1, 3]]));
+    assert_eq!(orders.from_wire(orders.to_wire(msg.clone())), msg);
+    assert_eq!(orders.to_wire(Message::from_pixels(1, &[[1, 2, 3]])), Message::from_pixels(1, &[[1, 2, 3]]));
+    assert_eq!(orders.to_wire(Message::from_data(2, &[0, 1], &[1, 2, 3])), Message::from_data(2, &[0, 1], &[1, 2, 3]));
+
+}
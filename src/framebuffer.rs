@@ -0,0 +1,148 @@
+//! Pixel state of every channel, updated by decoded messages.
+//!
+//! `Framebuffer` applies Set Pixel Colors messages the way the OPC specification describes:
+//! a message with data for n pixels sets the first n pixels of its channel and leaves the rest unchanged,
+//! data for more pixels than the channel has is ignored, and channel 0 sets every channel.
+//! It is also a server `Handler`, so it can sit behind any `Server`:
+//!
+//! ```rust
+//! use opc::framebuffer::Framebuffer;
+//! use opc::Message;
+//!
+//! let mut framebuffer = Framebuffer::new().channel(1, 4).channel(2, 2);
+//!
+//! framebuffer.apply(&Message::from_pixels(0, &[[1, 1, 1]; 3]));
+//! framebuffer.apply(&Message::from_pixels(2, &[[2, 2, 2]]));
+//!
+//! assert_eq!(framebuffer.pixels(1), Some(&[[1, 1, 1], [1, 1, 1], [1, 1, 1], [0, 0, 0]][..]));
+//! assert_eq!(framebuffer.pixels(2), Some(&[[2, 2, 2], [1, 1, 1]][..]));
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::{as_pixels, Command, Message, BROADCAST_CHANNEL};
+
+/// Pixel arrays of a fixed length for every configured channel, all starting black.
+#[derive (Clone, Debug, Default, PartialEq, Eq)]
+pub struct Framebuffer {
+    channels: BTreeMap<u8, Vec<[u8; 3]>>,
+}
+
+impl Framebuffer {
+    /// Create new Framebuffer without any channels
+    pub fn new() -> Framebuffer {
+        Framebuffer { channels: BTreeMap::new() }
+    }
+
+    /// Hold `len` pixels for a channel, which cannot be the broadcast channel 0
+    pub fn channel(mut self, channel: u8, len: usize) -> Framebuffer {
+        if channel != BROADCAST_CHANNEL {
+            self.channels.insert(channel, vec![[0; 3]; len]);
+        }
+        self
+    }
+
+    /// Apply a message, returning `false` if it does not set the pixels of any configured channel
+    pub fn apply(&mut self, msg: &Message) -> bool {
+        match msg.command {
+            Command::SetPixelColors { ref pixels } => self.set_pixels(msg.channel, pixels),
+            _ => false,
+        }
+    }
+
+    /// Set the first pixels of a channel, or of every channel on channel 0
+    ///
+    /// Returns `false` if the channel is not configured.
+    pub fn set_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) -> bool {
+        if channel == BROADCAST_CHANNEL {
+            for current in self.channels.values_mut() {
+                update(current, pixels);
+            }
+            return !self.channels.is_empty();
+        }
+        match self.channels.get_mut(&channel) {
+            Some(current) => {
+                update(current, pixels);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the first pixels of a channel from raw data, ignoring trailing bytes that do not fill a pixel
+    pub fn set_pixel_data(&mut self, channel: u8, data: &[u8]) -> bool {
+        self.set_pixels(channel, as_pixels(data))
+    }
+
+    /// The pixels of a configured channel
+    pub fn pixels(&self, channel: u8) -> Option<&[[u8; 3]]> {
+        self.channels.get(&channel).map(Vec::as_slice)
+    }
+
+    /// The configured channels and their pixels, in channel order
+    pub fn channels(&self) -> impl Iterator<Item = (u8, &[[u8; 3]])> {
+        self.channels.iter().map(|(&channel, pixels)| (channel, pixels.as_slice()))
+    }
+
+    /// Set every pixel of every channel to black
+    pub fn clear(&mut self) {
+        for pixels in self.channels.values_mut() {
+            pixels.iter_mut().for_each(|pixel| *pixel = [0; 3]);
+        }
+    }
+}
+
+/// Overwrite the first pixels, dropping any that do not fit
+fn update(current: &mut [[u8; 3]], pixels: &[[u8; 3]]) {
+    let len = std::cmp::min(current.len(), pixels.len());
+    current[..len].copy_from_slice(&pixels[..len]);
+}
+
+#[cfg(feature = "tokio")]
+impl crate::server::Handler for Framebuffer {
+    fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) {
+        self.set_pixels(channel, pixels);
+    }
+}
+
+#[test]
+fn should_update_first_pixels_only() {
+
+    let mut framebuffer = Framebuffer::new().channel(1, 3);
+
+    assert!(framebuffer.apply(&Message::from_pixels(1, &[[9; 3]; 3])));
+    assert!(framebuffer.set_pixel_data(1, &[1, 2, 3, 4, 5]));
+
+    assert_eq!(framebuffer.pixels(1), Some(&[[1, 2, 3], [9; 3], [9; 3]][..]));
+
+}
+
+#[test]
+fn should_ignore_oversize_and_unconfigured_channels() {
+
+    let mut framebuffer = Framebuffer::new().channel(1, 2).channel(0, 5);
+
+    assert!(framebuffer.apply(&Message::from_pixels(1, &[[1; 3], [2; 3], [3; 3]])));
+    assert!(!framebuffer.apply(&Message::from_pixels(4, &[[4; 3]])));
+    assert!(!framebuffer.apply(&Message::from_data(1, &[0, 1], &[5, 5, 5])));
+
+    assert_eq!(framebuffer.pixels(1), Some(&[[1; 3], [2; 3]][..]));
+    assert_eq!(framebuffer.pixels(0), None);
+    assert_eq!(framebuffer.channels().count(), 1);
+
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn should_apply_messages_dispatched_by_server() {
+
+    let server = crate::Server::new(Framebuffer::new().channel(1, 2).channel(2, 1));
+
+    server.dispatch(&Message::from_pixels(0, &[[7; 3], [8; 3]]));
+    server.dispatch(&Message::from_pixels(1, &[[1; 3]]));
+
+    let framebuffer = server.handler().lock().unwrap();
+    assert_eq!(framebuffer.pixels(1), Some(&[[1; 3], [8; 3]][..]));
+    assert_eq!(framebuffer.pixels(2), Some(&[[7; 3]][..]));
+
+}
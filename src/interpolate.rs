@@ -0,0 +1,274 @@
+//! Linear interpolation between received frames, to smooth low frame rate clients.
+//!
+//! Like Fadecandy, `Keyframes` fades every channel from where it currently is to each new frame
+//! over the time that passed between the last two frames, so output trails input by one frame.
+//! `Interpolated` wraps a server `Handler` and drives it with interpolated frames at a fixed output rate:
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "tokio")]
+//! # mod example {
+//! use std::time::Duration;
+//!
+//! use opc::interpolate::Interpolated;
+//! use opc::server::{Handler, Server};
+//!
+//! struct Print;
+//!
+//! impl Handler for Print {
+//!     fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) {
+//!         println!("channel {}: {:?}", channel, pixels.first());
+//!     }
+//! }
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let handler = Interpolated::spawn(Print, Duration::from_millis(10));
+//!     Server::new(handler).listen("127.0.0.1:7890").await.unwrap();
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Fade of a single channel
+#[derive (Clone, Debug)]
+struct Fade {
+    from: Vec<u8>,
+    to: Vec<u8>,
+    start: Instant,
+    duration: Duration,
+}
+
+impl Fade {
+    fn sample(&self, now: Instant) -> Vec<u8> {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= self.duration {
+            return self.to.clone();
+        }
+        let t = elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        self.from
+            .iter()
+            .zip(&self.to)
+            .map(|(&from, &to)| (from as f32 + (to as f32 - from as f32) * t).round() as u8)
+            .collect()
+    }
+}
+
+/// The latest frames of every channel, interpolated over time.
+///
+/// Frames are raw Set Pixel Colors data and every byte is interpolated on its own,
+/// so any 8-bit `PixelFormat` works.
+#[derive (Clone, Debug)]
+pub struct Keyframes {
+    fades: BTreeMap<u8, Fade>,
+    max_duration: Duration,
+}
+
+impl Keyframes {
+    /// Create new Keyframes, fading for at most one second
+    pub fn new() -> Keyframes {
+        Keyframes {
+            fades: BTreeMap::new(),
+            max_duration: Duration::from_secs(1),
+        }
+    }
+
+    /// Limit how long a fade lasts, so a frame after a pause does not crawl in
+    pub fn max_duration(mut self, max_duration: Duration) -> Keyframes {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// Start fading a channel to a new frame received at `now`
+    ///
+    /// The first frame of a channel is shown right away.
+    /// Data past the end of the current frame is not interpolated.
+    pub fn push(&mut self, channel: u8, data: &[u8], now: Instant) {
+        let max_duration = self.max_duration;
+        let fade = match self.fades.get_mut(&channel) {
+            Some(fade) => fade,
+            None => {
+                self.fades.insert(channel, Fade {
+                    from: data.to_vec(),
+                    to: data.to_vec(),
+                    start: now,
+                    duration: Duration::ZERO,
+                });
+                return;
+            }
+        };
+
+        let mut from = fade.sample(now);
+        from.truncate(data.len());
+        from.extend_from_slice(&data[from.len()..]);
+
+        fade.duration = std::cmp::min(now.saturating_duration_since(fade.start), max_duration);
+        fade.start = now;
+        fade.from = from;
+        fade.to = data.to_vec();
+    }
+
+    /// The interpolated frame of a channel at `now`
+    pub fn sample(&self, channel: u8, now: Instant) -> Option<Vec<u8>> {
+        self.fades.get(&channel).map(|fade| fade.sample(now))
+    }
+
+    /// Interpolated frames of every channel at `now`
+    pub fn sample_all(&self, now: Instant) -> impl Iterator<Item = (u8, Vec<u8>)> + '_ {
+        self.fades.iter().map(move |(&channel, fade)| (channel, fade.sample(now)))
+    }
+
+    /// Forget the frames of a channel
+    pub fn remove(&mut self, channel: u8) {
+        self.fades.remove(&channel);
+    }
+}
+
+impl Default for Keyframes {
+    fn default() -> Keyframes {
+        Keyframes::new()
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub use self::stage::Interpolated;
+
+#[cfg(feature = "tokio")]
+mod stage {
+    use std::sync::{Arc, Mutex, Weak};
+    use std::time::{Duration, Instant};
+
+    use tokio::time::MissedTickBehavior;
+
+    use super::Keyframes;
+    use crate::server::Handler;
+    use crate::OpcError;
+
+    /// A `Handler` that collects frames as `Keyframes` and passes interpolated frames on to another handler.
+    ///
+    /// A background task delivers the interpolated frame of every channel once per output interval,
+    /// until this handler is dropped. Other messages are passed on as they arrive.
+    #[derive (Debug)]
+    pub struct Interpolated<H> {
+        keyframes: Arc<Mutex<Keyframes>>,
+        downstream: Arc<Mutex<H>>,
+    }
+
+    impl<H: Handler> Interpolated<H> {
+        /// Spawn the output task on the current tokio runtime, delivering frames to `downstream` every `interval`
+        pub fn spawn(downstream: H, interval: Duration) -> Interpolated<H> {
+            Interpolated::with_keyframes(downstream, interval, Keyframes::new())
+        }
+
+        /// Spawn the output task, interpolating with custom keyframes
+        pub fn with_keyframes(downstream: H, interval: Duration, keyframes: Keyframes) -> Interpolated<H> {
+            let keyframes = Arc::new(Mutex::new(keyframes));
+            let downstream = Arc::new(Mutex::new(downstream));
+            tokio::spawn(run(Arc::downgrade(&keyframes), downstream.clone(), interval));
+            Interpolated { keyframes, downstream }
+        }
+
+        /// The handler receiving interpolated frames
+        pub fn downstream(&self) -> &Arc<Mutex<H>> {
+            &self.downstream
+        }
+    }
+
+    impl<H: Handler> Handler for Interpolated<H> {
+        fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) {
+            self.on_pixel_data(channel, pixels.as_flattened());
+        }
+
+        fn on_pixel_data(&mut self, channel: u8, data: &[u8]) {
+            self.keyframes.lock().unwrap().push(channel, data, Instant::now());
+        }
+
+        fn on_sysex(&mut self, channel: u8, id: [u8; 2], data: &[u8]) {
+            self.downstream.lock().unwrap().on_sysex(channel, id, data);
+        }
+
+        fn on_unknown(&mut self, channel: u8, command: u8, data: &[u8]) {
+            self.downstream.lock().unwrap().on_unknown(channel, command, data);
+        }
+
+        fn on_error(&mut self, err: &OpcError) {
+            self.downstream.lock().unwrap().on_error(err);
+        }
+    }
+
+    async fn run<H: Handler>(keyframes: Weak<Mutex<Keyframes>>, downstream: Arc<Mutex<H>>, interval: Duration) {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let frames: Vec<_> = match keyframes.upgrade() {
+                Some(keyframes) => keyframes.lock().unwrap().sample_all(Instant::now()).collect(),
+                None => return,
+            };
+            let mut downstream = downstream.lock().unwrap();
+            for (channel, data) in frames {
+                downstream.on_pixel_data(channel, &data);
+            }
+        }
+    }
+}
+
+#[test]
+fn should_fade_over_previous_frame_interval() {
+
+    let start = Instant::now();
+    let mut keyframes = Keyframes::new();
+
+    keyframes.push(1, &[0, 100, 200], start);
+    assert_eq!(keyframes.sample(1, start), Some(vec![0, 100, 200]));
+
+    keyframes.push(1, &[100, 100, 0], start + Duration::from_millis(100));
+    assert_eq!(keyframes.sample(1, start + Duration::from_millis(100)), Some(vec![0, 100, 200]));
+    assert_eq!(keyframes.sample(1, start + Duration::from_millis(150)), Some(vec![50, 100, 100]));
+    assert_eq!(keyframes.sample(1, start + Duration::from_millis(250)), Some(vec![100, 100, 0]));
+    assert_eq!(keyframes.sample(2, start), None);
+
+}
+
+#[test]
+fn should_start_next_fade_from_current_output() {
+
+    let start = Instant::now();
+    let mut keyframes = Keyframes::new().max_duration(Duration::from_millis(100));
+
+    keyframes.push(1, &[0], start);
+    // A long pause fades for at most the maximum duration
+    keyframes.push(1, &[200], start + Duration::from_secs(10));
+    let middle = start + Duration::from_millis(10050);
+    assert_eq!(keyframes.sample(1, middle), Some(vec![100]));
+
+    // Interrupting a fade continues from the current output, and new pixels appear right away
+    keyframes.push(1, &[0, 50], middle);
+    assert_eq!(keyframes.sample(1, middle), Some(vec![100, 50]));
+    assert_eq!(keyframes.sample(1, middle + Duration::from_millis(25)), Some(vec![50, 50]));
+
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn should_deliver_interpolated_frames() {
+    use crate::server::Handler;
+
+    struct Record(tokio::sync::mpsc::UnboundedSender<(u8, Vec<[u8; 3]>)>);
+
+    impl Handler for Record {
+        fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) {
+            let _ = self.0.send((channel, pixels.to_vec()));
+        }
+    }
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut handler = Interpolated::spawn(Record(tx), Duration::from_millis(5));
+
+    handler.on_pixels(3, &[[10, 20, 30]]);
+
+    assert_eq!(rx.recv().await.unwrap(), (3, vec![[10, 20, 30]]));
+
+}
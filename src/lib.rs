@@ -4,63 +4,85 @@
 //!     Open Pixel Control is a protocol that is used to control arrays of RGB lights
 //!     like [Total Control Lighting](http://www.coolneon.com/) and [Fadecandy devices](https://github.com/scanlime/fadecandy).
 //!     
+//!     `OpcCodec` implements the `tokio_util::codec` traits for tokio 1.x (the default `tokio` feature),
+//!     and the `tokio_io::codec` traits for tokio-io 0.1 (the `legacy` feature).
 //!     
 //!     # Examples
 //!     Setup Server to Listen for Messages: 
 //!     
 //!     ```rust,no_run
-//!     extern crate opc;
-//!     extern crate futures;
-//!     extern crate tokio_core;
-//!     extern crate tokio_io;
-//!     
+//!     # #[cfg(feature = "tokio")]
+//!     # mod example {
 //!     use opc::OpcCodec;
-//!     use futures::{Future, Stream};
-//!     
-//!     use tokio_io::AsyncRead;
-//!     use tokio_core::net::TcpListener;
-//!     use tokio_core::reactor::Core;
+//!     use futures::StreamExt;
 //!     
-//!     fn main() {
-//!         let mut core = Core::new().unwrap();
-//!         let handle = core.handle();
-//!         let remote_addr = "127.0.0.1:7890".parse().unwrap();
+//!     use tokio::net::TcpListener;
+//!     use tokio_util::codec::Framed;
 //!     
-//!         let listener = TcpListener::bind(&remote_addr, &handle).unwrap();
+//!     #[tokio::main]
+//!     async fn main() {
+//!         let listener = TcpListener::bind("127.0.0.1:7890").await.unwrap();
 //!     
 //!         // Accept all incoming sockets
-//!         let server = listener.incoming().for_each(move |(socket, _)| {
-//!             // `OpcCodec` handles encoding / decoding frames.
-//!             let transport = socket.framed(OpcCodec);
-//!     
-//!             let process_connection = transport.for_each(|message| {
-//!                 println!("GOT: {:?}", message);
-//!                 Ok(())
-//!             });
+//!         loop {
+//!             let (socket, _) = listener.accept().await.unwrap();
 //!     
 //!             // Spawn a new task dedicated to processing the connection
-//!             handle.spawn(process_connection.map_err(|_| ()));
+//!             tokio::spawn(async move {
+//!                 // `OpcCodec` handles encoding / decoding frames.
+//!                 let mut transport = Framed::new(socket, OpcCodec::new());
 //!     
-//!             Ok(())
-//!         });
-//!     
-//!         // Open listener
-//!         core.run(server).unwrap();
+//!                 while let Some(Ok(message)) = transport.next().await {
+//!                     println!("GOT: {:?}", message);
+//!                 }
+//!             });
+//!         }
 //!     }
+//!     # }
+//!     # fn main() {}
 //!     ```
 
-extern crate tokio_io;
-extern crate bytes;
-
-use std::io;
-
-use tokio_io::codec::{Encoder, Decoder};
-use bytes::{BytesMut, BufMut, Buf, IntoBuf, BigEndian};
+mod address;
+mod codec;
+mod error;
+pub mod artnet;
+pub mod blocking;
+#[cfg(feature = "tokio")]
+pub mod client;
+pub mod correction;
+pub mod dither;
+#[cfg(feature = "fadecandy")]
+pub mod fadecandy;
+pub mod format;
+pub mod framebuffer;
+pub mod interpolate;
+pub mod parser;
+mod pixels;
+pub mod queue;
+#[cfg(feature = "tokio")]
+pub mod server;
+pub mod sysex;
+#[cfg(feature = "tokio")]
+pub mod udp;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use crate::address::{Address, ParseAddressError};
+#[cfg(feature = "tokio")]
+pub use crate::client::Client;
+pub use crate::codec::{OpcCodec, OversizePolicy, UnknownCommandPolicy};
+pub use crate::error::OpcError;
+pub use crate::format::{ColorOrder, ColorOrders, PixelFormat};
+pub use crate::parser::{CommandRef, FrameParser, MessageRef};
+pub use crate::pixels::{as_pixels, Pixels};
+#[cfg(feature = "tokio")]
+pub use crate::server::Server;
 
 /// Default openpixel tcp port
 pub const DEFAULT_OPC_PORT: usize = 7890;
 
 const MAX_MESSAGE_SIZE: usize = 0xffff;
+const MAX_PIXELS_PER_MESSAGE: usize = MAX_MESSAGE_SIZE / 3;
 const SYS_EXCLUSIVE: u8 = 0xff;
 const SET_PIXEL_COLORS: u8 = 0x00;
 const BROADCAST_CHANNEL: u8 = 0;
@@ -72,8 +94,11 @@ pub enum Command {
     SetPixelColors {
         /// If the data block has length 3*n, then the first n pixels of the specified channel are set.
         /// All other pixels are unaffected and retain their current colour values.
-        /// If the data length is not a multiple of 3, or there is data for more pixels than are present, the extra data is ignored.
-        pixels: Vec<[u8; 3]>,
+        /// If the data length is not a multiple of 3, or there is data for more pixels than are present, receivers ignore the extra data.
+        ///
+        /// Decoding keeps trailing bytes that do not fill an RGB pixel, for pixel formats of other sizes,
+        /// so they count towards `Message::len` and equality and are encoded again unchanged.
+        pixels: Pixels,
     },
     /// Used to send a message that is specific to a particular device or software system.
     SystemExclusive {
@@ -82,6 +107,13 @@ pub enum Command {
         /// designers of that system are then free to define any message format for the rest of the data block.
         data: Vec<u8>,
     },
+    /// Any command this crate does not interpret, kept so it can be forwarded verbatim.
+    Unknown {
+        /// The raw command byte.
+        command: u8,
+        /// The raw data block.
+        data: Vec<u8>,
+    },
 }
 
 /// Describes a single message that follows the OPC protocol
@@ -100,7 +132,15 @@ impl Message {
     pub fn from_pixels(ch: u8, pixels: &[[u8; 3]]) -> Message {
         Message {
             channel: ch,
-            command: Command::SetPixelColors { pixels: pixels.to_owned() },
+            command: Command::SetPixelColors { pixels: Pixels::copy_from_slice(pixels) },
+        }
+    }
+
+    /// Create new Message Instance from pixels in any Pixel Format
+    pub fn from_format<F: PixelFormat>(ch: u8, pixels: &[F::Pixel]) -> Message {
+        Message {
+            channel: ch,
+            command: Command::SetPixelColors { pixels: Pixels::from_format::<F>(pixels) },
         }
     }
 
@@ -115,14 +155,33 @@ impl Message {
         }
     }
 
+    /// Borrow as a Message Reference, which `OpcCodec` encodes without cloning
+    pub fn as_ref(&self) -> MessageRef<'_> {
+        let command = match self.command {
+            Command::SetPixelColors { ref pixels } => CommandRef::SetPixelColors { data: pixels.as_bytes() },
+            Command::SystemExclusive { id, ref data } => CommandRef::SystemExclusive { id, data },
+            Command::Unknown { command, ref data } => CommandRef::Unknown { command, data },
+        };
+        MessageRef {
+            channel: self.channel,
+            command,
+        }
+    }
+
     /// Check Message Data Length
     pub fn len(&self) -> usize {
         match self.command {
-            Command::SetPixelColors { ref pixels } => pixels.len() * 3,
+            Command::SetPixelColors { ref pixels } => pixels.as_bytes().len(),
             Command::SystemExclusive { id: _, ref data } => data.len() + 2,
+            Command::Unknown { command: _, ref data } => data.len(),
         }
     }
 
+    /// Check if Message carries no data
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Check is Message has a valid size
     pub fn is_valid(&self) -> bool {
         self.len() <= MAX_MESSAGE_SIZE
@@ -134,143 +193,3 @@ impl Message {
     }
 }
 
-/// Open Pixel Codec Instance
-pub struct OpcCodec;
-
-impl Decoder for OpcCodec {
-    type Item = Message;
-    type Error = io::Error;
-
-    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Self::Item>> {
-
-        let (msg, length) = {
-            // Get Temporary Src
-            let mut src = src.clone();
-
-            // Check if buf length is more than 4;
-            if src.len() < 4 {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid Message Command"));
-            };
-            let mut buf = src.split_to(4).into_buf();
-
-            let (channel, command) = (buf.get_u8(), buf.get_u8());
-            let length = buf.get_u16::<BigEndian>() as usize;
-
-            if src.len() < length {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid Message Command"));
-            }
-            let mut buf = src.split_to(length).into_buf();
-
-            let msg = match command {
-                SET_PIXEL_COLORS => {
-                    let pixels: Vec<_> = buf.bytes()[..length - (length % 3)]
-                        .chunks(3)
-                        .map(|chunk| [chunk[0], chunk[1], chunk[2]])
-                        .collect();
-                    Message {
-                        channel: channel,
-                        command: Command::SetPixelColors { pixels: pixels },
-                    }
-                }
-                SYS_EXCLUSIVE => {
-                    Message {
-                        channel: channel,
-                        command: Command::SystemExclusive {
-                            id: [buf.get_u8(), buf.get_u8()],
-                            data: buf.collect(),
-                        },
-                    }
-                }
-                // TODO: What to do if incorrect?
-                _ => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidData,
-                                              "Invalid Message Command"))
-                }
-            };
-
-            (msg, length + 4)
-        };
-
-        // Advance
-        src.split_to(length);
-        Ok(Some(msg))
-    }
-}
-
-impl Encoder for OpcCodec {
-    type Item = Message;
-    type Error = io::Error;
-
-    fn encode(&mut self, msg: Self::Item, dst: &mut BytesMut) -> io::Result<()> {
-
-        let ser_len = msg.len();
-        dst.reserve(4 + ser_len);
-
-        match msg.command {
-            Command::SetPixelColors { pixels } => {
-
-                // Insert Channel and Command
-                dst.put_slice(&[msg.channel, SET_PIXEL_COLORS]);
-                // Insert Data Length
-                dst.put_u16::<BigEndian>(ser_len as u16);
-
-                // Insert Data
-                for pixel in pixels {
-                    dst.put_slice(&pixel);
-                }
-            }
-            Command::SystemExclusive { id, data } => {
-
-                // Insert Channel and Command
-                dst.put_slice(&[msg.channel, SYS_EXCLUSIVE]);
-                // Insert Data Length
-                dst.put_u16::<BigEndian>(ser_len as u16);
-
-                // Insert Data
-                dst.put_slice(&id);
-                dst.put_slice(&data);
-            }
-        }
-
-        Ok(())
-    }
-}
-
-#[test]
-fn should_roundtrip_pixel_command() {
-
-    let mut codec = OpcCodec;
-    let mut buf = BytesMut::new();
-    let test_msg = Message {
-        channel: 4,
-        command: Command::SetPixelColors { pixels: vec![[9; 3]; 10] },
-    };
-
-    assert!(codec.encode(test_msg.clone(), &mut buf).is_ok());
-
-    let recv_msg = codec.decode(&mut buf.into()).unwrap().unwrap();
-
-    assert_eq!(test_msg, recv_msg);
-
-}
-
-#[test]
-fn server_roundtrip_system_command() {
-
-    let mut codec = OpcCodec;
-    let mut buf = BytesMut::new();
-    let test_msg = Message {
-        channel: 4,
-        command: Command::SystemExclusive {
-            id: [0; 2],
-            data: vec![8; 10],
-        },
-    };
-
-    assert!(codec.encode(test_msg.clone(), &mut buf).is_ok());
-
-    let recv_msg = codec.decode(&mut buf.into()).unwrap().unwrap();
-
-    assert_eq!(test_msg, recv_msg);
-
-}
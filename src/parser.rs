@@ -0,0 +1,303 @@
+//! Runtime independent OPC framing.
+//!
+//! `FrameParser` walks a byte slice and yields borrowed `MessageRef`s without copying or allocating,
+//! so the same parsing code can sit behind `OpcCodec`, a blocking `std::io::Read` loop,
+//! or any other transport:
+//!
+//! ```rust,no_run
+//! use std::io::Read;
+//! use std::net::TcpStream;
+//!
+//! use opc::parser::FrameParser;
+//!
+//! let mut socket = TcpStream::connect("127.0.0.1:7890").unwrap();
+//! let mut buf = Vec::new();
+//! let mut chunk = [0; 4096];
+//!
+//! loop {
+//!     let n = socket.read(&mut chunk).unwrap();
+//!     if n == 0 {
+//!         break;
+//!     }
+//!     buf.extend_from_slice(&chunk[..n]);
+//!
+//!     let mut parser = FrameParser::new(&buf);
+//!     for msg in &mut parser {
+//!         println!("GOT: {:?}", msg);
+//!     }
+//!     let consumed = parser.consumed();
+//!     buf.drain(..consumed);
+//! }
+//! ```
+
+use bytes::Bytes;
+
+use crate::{Command, Message, OpcError, Pixels};
+use crate::{BROADCAST_CHANNEL, MAX_MESSAGE_SIZE, SET_PIXEL_COLORS, SYS_EXCLUSIVE};
+
+/// Length of the channel, command and length fields preceding every frame's data.
+pub const HEADER_LEN: usize = 4;
+
+/// Describes a borrowed OPC Command.
+#[derive (Clone, Copy, Debug, PartialEq)]
+pub enum CommandRef<'a> {
+    /// RGB values for the first pixels of the channel.
+    SetPixelColors {
+        /// Pixels in red, green, blue order, viewed as pixels with `as_pixels`.
+        data: &'a [u8],
+    },
+    /// A message that is specific to a particular device or software system.
+    SystemExclusive {
+        /// The two-byte system ID.
+        id: [u8; 2],
+        /// The rest of the data block.
+        data: &'a [u8],
+    },
+    /// Any command this crate does not interpret.
+    Unknown {
+        /// The raw command byte.
+        command: u8,
+        /// The raw data block.
+        data: &'a [u8],
+    },
+}
+
+/// Describes a single message that borrows its data, from a read buffer or from a `Message`
+#[derive (Clone, Copy, Debug, PartialEq)]
+pub struct MessageRef<'a> {
+    /// Channel the message is addressed to, 0 being broadcast.
+    pub channel: u8,
+    /// Designates the message type
+    pub command: CommandRef<'a>,
+}
+
+impl<'a> MessageRef<'a> {
+    /// Create new Message Reference from Pixel Array
+    pub fn from_pixels(ch: u8, pixels: &'a [[u8; 3]]) -> MessageRef<'a> {
+        MessageRef {
+            channel: ch,
+            command: CommandRef::SetPixelColors { data: pixels.as_flattened() },
+        }
+    }
+
+    /// Create new Message Reference from Data Array
+    pub fn from_data(ch: u8, id: &[u8; 2], data: &'a [u8]) -> MessageRef<'a> {
+        MessageRef {
+            channel: ch,
+            command: CommandRef::SystemExclusive { id: *id, data },
+        }
+    }
+
+    /// Check Message Data Length
+    pub fn len(&self) -> usize {
+        match self.command {
+            CommandRef::SetPixelColors { data } => data.len(),
+            CommandRef::SystemExclusive { data, .. } => data.len() + 2,
+            CommandRef::Unknown { data, .. } => data.len(),
+        }
+    }
+
+    /// Check if Message carries no data
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check is Message has a valid size
+    pub fn is_valid(&self) -> bool {
+        self.len() <= MAX_MESSAGE_SIZE
+    }
+
+    /// Check if Message is a broadcast message
+    pub fn is_broadcast(&self) -> bool {
+        self.channel == BROADCAST_CHANNEL
+    }
+
+    /// Copy into an owned Message
+    pub fn to_message(&self) -> Message {
+        let command = match self.command {
+            CommandRef::SetPixelColors { data } => {
+                Command::SetPixelColors { pixels: Pixels::from_bytes(Bytes::copy_from_slice(data)) }
+            }
+            CommandRef::SystemExclusive { id, data } => {
+                Command::SystemExclusive {
+                    id,
+                    data: data.to_vec(),
+                }
+            }
+            CommandRef::Unknown { command, data } => {
+                Command::Unknown {
+                    command,
+                    data: data.to_vec(),
+                }
+            }
+        };
+        Message {
+            channel: self.channel,
+            command,
+        }
+    }
+}
+
+impl<'a> From<MessageRef<'a>> for Message {
+    fn from(msg: MessageRef<'a>) -> Message {
+        msg.to_message()
+    }
+}
+
+impl<'a> From<&'a Message> for MessageRef<'a> {
+    fn from(msg: &'a Message) -> MessageRef<'a> {
+        msg.as_ref()
+    }
+}
+
+/// Total length of the frame at the start of `buf`, once its header is available.
+pub fn frame_len(buf: &[u8]) -> Option<usize> {
+    if buf.len() < HEADER_LEN {
+        return None;
+    }
+    Some(HEADER_LEN + u16::from_be_bytes([buf[2], buf[3]]) as usize)
+}
+
+/// Parse the frame at the start of `buf`.
+///
+/// Returns `None` until the whole frame is available.
+/// Otherwise returns the parsed message together with the length of the frame,
+/// which should be skipped even if the message is malformed.
+pub fn parse_frame(buf: &[u8]) -> Option<(Result<MessageRef<'_>, OpcError>, usize)> {
+    let len = match frame_len(buf) {
+        Some(len) if len <= buf.len() => len,
+        _ => return None,
+    };
+    let (channel, command, data) = (buf[0], buf[1], &buf[HEADER_LEN..len]);
+
+    let command = match command {
+        SET_PIXEL_COLORS => Ok(CommandRef::SetPixelColors { data }),
+        SYS_EXCLUSIVE if data.len() < 2 => Err(OpcError::SysExTooShort),
+        SYS_EXCLUSIVE => {
+            Ok(CommandRef::SystemExclusive {
+                id: [data[0], data[1]],
+                data: &data[2..],
+            })
+        }
+        command => Ok(CommandRef::Unknown { command, data }),
+    };
+
+    Some((command.map(|command| MessageRef { channel, command }), len))
+}
+
+/// Encode the header of a frame carrying `len` bytes of data.
+pub fn header(channel: u8, command: u8, len: u16) -> [u8; HEADER_LEN] {
+    let len = len.to_be_bytes();
+    [channel, command, len[0], len[1]]
+}
+
+/// Iterates over the complete frames in a byte slice.
+///
+/// Iteration stops at the first incomplete frame. A malformed frame yields an error and is skipped.
+#[derive (Clone, Debug)]
+pub struct FrameParser<'a> {
+    buf: &'a [u8],
+    consumed: usize,
+}
+
+impl<'a> FrameParser<'a> {
+    /// Create new Parser over a buffer that starts at a frame boundary
+    pub fn new(buf: &'a [u8]) -> FrameParser<'a> {
+        FrameParser { buf, consumed: 0 }
+    }
+
+    /// Number of bytes taken up by the frames yielded so far
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /// Bytes that have not been parsed yet, starting at a frame boundary
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.consumed..]
+    }
+
+    /// Number of bytes still missing from the next frame, if its header is available
+    pub fn needed(&self) -> Option<usize> {
+        frame_len(self.remaining()).map(|len| len.saturating_sub(self.remaining().len()))
+    }
+}
+
+impl<'a> Iterator for FrameParser<'a> {
+    type Item = Result<MessageRef<'a>, OpcError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (msg, len) = parse_frame(self.remaining())?;
+        self.consumed += len;
+        Some(msg)
+    }
+}
+
+#[test]
+fn should_parse_borrowed_frames() {
+
+    let buf = [1, SET_PIXEL_COLORS, 0, 7, 1, 2, 3, 4, 5, 6, 7,
+               2, SYS_EXCLUSIVE, 0, 3, 0, 1, 9,
+               3, 0x42, 0, 1, 8];
+
+    let msgs: Vec<_> = FrameParser::new(&buf).map(Result::unwrap).collect();
+
+    assert_eq!(msgs, vec![
+        MessageRef { channel: 1, command: CommandRef::SetPixelColors { data: &[1, 2, 3, 4, 5, 6, 7] } },
+        MessageRef { channel: 2, command: CommandRef::SystemExclusive { id: [0, 1], data: &[9] } },
+        MessageRef { channel: 3, command: CommandRef::Unknown { command: 0x42, data: &[8] } },
+    ]);
+
+}
+
+#[test]
+fn should_stop_at_incomplete_frame() {
+
+    let buf = [1, SET_PIXEL_COLORS, 0, 3, 1, 2, 3, 2, SET_PIXEL_COLORS, 0, 6, 1];
+    let mut parser = FrameParser::new(&buf);
+
+    assert!(parser.next().is_some());
+    assert!(parser.next().is_none());
+    assert_eq!(parser.consumed(), 7);
+    assert_eq!(parser.remaining(), &buf[7..]);
+    assert_eq!(parser.needed(), Some(5));
+
+    assert_eq!(FrameParser::new(&buf[..2]).needed(), None);
+
+}
+
+#[test]
+fn should_skip_malformed_frame() {
+
+    let buf = [1, SYS_EXCLUSIVE, 0, 1, 0, 2, SET_PIXEL_COLORS, 0, 0];
+    let mut parser = FrameParser::new(&buf);
+
+    match parser.next() {
+        Some(Err(OpcError::SysExTooShort)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(parser.next().unwrap().unwrap().channel, 2);
+    assert_eq!(parser.consumed(), buf.len());
+
+}
+
+#[test]
+fn should_borrow_owned_message() {
+
+    let pixels = [[1, 2, 3], [4, 5, 6]];
+    let msg = Message::from_pixels(1, &pixels);
+
+    assert_eq!(msg.as_ref(), MessageRef::from_pixels(1, &pixels));
+    assert_eq!(msg.as_ref().len(), msg.len());
+    assert_eq!(msg.as_ref().to_message(), msg);
+
+    let msg = Message::from_data(2, &[0, 1], &[7]);
+    assert_eq!(MessageRef::from(&msg), MessageRef::from_data(2, &[0, 1], &[7]));
+
+}
+
+#[test]
+fn should_encode_header() {
+
+    assert_eq!(header(7, SYS_EXCLUSIVE, 0x1234), [7, SYS_EXCLUSIVE, 0x12, 0x34]);
+
+}
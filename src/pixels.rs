@@ -0,0 +1,153 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Deref;
+
+use bytes::Bytes;
+
+use crate::format::{self, PixelFormat};
+
+/// Pixel data of a Set Pixel Colors message, three bytes in red, green, blue order for each pixel.
+///
+/// The data lives in a shared `Bytes` buffer, so decoded pixels are a view into the read buffer
+/// and cloning a frame does not copy it. Dereferences to the complete pixels,
+/// while trailing bytes that do not fill a pixel are kept and sent along unchanged.
+#[derive (Clone, Default, PartialEq, Eq, Hash)]
+pub struct Pixels {
+    data: Bytes,
+}
+
+impl Pixels {
+    /// Create new empty Pixels
+    pub fn new() -> Pixels {
+        Pixels { data: Bytes::new() }
+    }
+
+    /// Wrap raw pixel data without copying it
+    pub fn from_bytes(data: Bytes) -> Pixels {
+        Pixels { data }
+    }
+
+    /// Copy pixels into a new buffer
+    pub fn copy_from_slice(pixels: &[[u8; 3]]) -> Pixels {
+        Pixels { data: Bytes::copy_from_slice(pixels.as_flattened()) }
+    }
+
+    /// Encode pixels of any Pixel Format into a new buffer
+    pub fn from_format<F: PixelFormat>(pixels: &[F::Pixel]) -> Pixels {
+        Pixels { data: format::encode::<F>(pixels).into() }
+    }
+
+    /// Decode the raw data as pixels of any Pixel Format
+    pub fn to_format<F: PixelFormat>(&self) -> Vec<F::Pixel> {
+        format::decode::<F>(&self.data)
+    }
+
+    /// The raw data, including trailing bytes that do not fill a pixel
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Unwrap the raw data
+    pub fn into_bytes(self) -> Bytes {
+        self.data
+    }
+
+    /// The complete pixels
+    pub fn as_slice(&self) -> &[[u8; 3]] {
+        as_pixels(&self.data)
+    }
+}
+
+/// View raw data as pixels, dropping trailing bytes that do not fill a pixel.
+pub fn as_pixels(data: &[u8]) -> &[[u8; 3]] {
+    data.as_chunks().0
+}
+
+impl Deref for Pixels {
+    type Target = [[u8; 3]];
+
+    fn deref(&self) -> &[[u8; 3]] {
+        self.as_slice()
+    }
+}
+
+impl AsRef<[[u8; 3]]> for Pixels {
+    fn as_ref(&self) -> &[[u8; 3]] {
+        self.as_slice()
+    }
+}
+
+/// Lists the pixels, followed by the trailing bytes that do not fill a pixel, if any
+impl fmt::Debug for Pixels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (pixels, rest) = self.data.as_chunks::<3>();
+        let mut list = f.debug_list();
+        list.entries(pixels);
+        if !rest.is_empty() {
+            list.entry(&rest);
+        }
+        list.finish()
+    }
+}
+
+impl From<Bytes> for Pixels {
+    fn from(data: Bytes) -> Pixels {
+        Pixels::from_bytes(data)
+    }
+}
+
+impl From<Vec<[u8; 3]>> for Pixels {
+    fn from(pixels: Vec<[u8; 3]>) -> Pixels {
+        let data: Vec<u8> = pixels.into_flattened();
+        Pixels { data: data.into() }
+    }
+}
+
+impl<'a> From<&'a [[u8; 3]]> for Pixels {
+    fn from(pixels: &'a [[u8; 3]]) -> Pixels {
+        Pixels::copy_from_slice(pixels)
+    }
+}
+
+impl FromIterator<[u8; 3]> for Pixels {
+    fn from_iter<I: IntoIterator<Item = [u8; 3]>>(iter: I) -> Pixels {
+        let data: Vec<u8> = iter.into_iter().flatten().collect();
+        Pixels { data: data.into() }
+    }
+}
+
+#[test]
+fn should_view_complete_pixels() {
+
+    let pixels = Pixels::from_bytes(Bytes::from_static(&[1, 2, 3, 4, 5, 6, 7]));
+
+    assert_eq!(pixels.len(), 2);
+    assert_eq!(&pixels[..], &[[1, 2, 3], [4, 5, 6]]);
+    assert_eq!(pixels.as_bytes().len(), 7);
+    assert_eq!(format!("{:?}", pixels), "[[1, 2, 3], [4, 5, 6], [7]]");
+
+}
+
+#[test]
+fn should_build_from_pixel_arrays() {
+
+    let from_vec = Pixels::from(vec![[1, 2, 3], [4, 5, 6]]);
+    let from_slice = Pixels::copy_from_slice(&[[1, 2, 3], [4, 5, 6]]);
+    let from_iter: Pixels = vec![[1, 2, 3], [4, 5, 6]].into_iter().collect();
+
+    assert_eq!(from_vec, from_slice);
+    assert_eq!(from_vec, from_iter);
+    assert_eq!(from_vec.as_bytes(), &[1, 2, 3, 4, 5, 6]);
+
+}
+
+#[test]
+fn should_convert_pixel_formats() {
+
+    let pixels = Pixels::from_format::<format::Rgbw8>(&[[1, 2, 3, 4], [5, 6, 7, 8]]);
+
+    assert_eq!(pixels.as_bytes(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(pixels.to_format::<format::Rgbw8>(), vec![[1, 2, 3, 4], [5, 6, 7, 8]]);
+    assert_eq!(pixels.to_format::<format::Rgb8>(), vec![[1, 2, 3], [4, 5, 6]]);
+
+}
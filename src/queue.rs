@@ -0,0 +1,241 @@
+//! Latest-frame-wins queueing for senders that outpace their server.
+//!
+//! When frames are rendered faster than the server accepts them, queueing all of them only adds lag.
+//! `FrameQueue` keeps at most one pending Set Pixel Colors message per channel, dropping the older one,
+//! and `Coalesce` puts such a queue in front of any `Sink` of messages.
+
+use std::collections::VecDeque;
+
+use crate::{Command, Message};
+
+/// Pending messages in which only the newest Set Pixel Colors message of each channel is kept.
+///
+/// Other messages are never dropped and keep their order.
+#[derive (Clone, Debug)]
+pub struct FrameQueue {
+    pending: VecDeque<Message>,
+    dropped: u64,
+    dropped_per_channel: Box<[u64; 256]>,
+}
+
+impl FrameQueue {
+    /// Create new empty Queue
+    pub fn new() -> FrameQueue {
+        FrameQueue {
+            pending: VecDeque::new(),
+            dropped: 0,
+            dropped_per_channel: Box::new([0; 256]),
+        }
+    }
+
+    /// Queue a message. Returns `true` if it replaced a pending frame of the same channel.
+    ///
+    /// A replacing frame moves to the back of the queue, so it still follows any message queued before it.
+    pub fn push(&mut self, msg: Message) -> bool {
+        let replaced = match msg.command {
+            Command::SetPixelColors { .. } => {
+                match self.pending.iter().position(|pending| is_pixels_on(pending, msg.channel)) {
+                    Some(index) => {
+                        self.pending.remove(index);
+                        self.dropped += 1;
+                        self.dropped_per_channel[msg.channel as usize] += 1;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => false,
+        };
+        self.pending.push_back(msg);
+        replaced
+    }
+
+    /// Take the oldest pending message
+    pub fn pop(&mut self) -> Option<Message> {
+        self.pending.pop_front()
+    }
+
+    /// Check if a Set Pixel Colors message is pending for a channel
+    pub fn has_pixels(&self, channel: u8) -> bool {
+        self.pending.iter().any(|pending| is_pixels_on(pending, channel))
+    }
+
+    /// Number of pending messages
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Check if no message is pending
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Number of frames replaced before they were sent
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Number of frames replaced before they were sent on a single channel
+    pub fn dropped_on(&self, channel: u8) -> u64 {
+        self.dropped_per_channel[channel as usize]
+    }
+}
+
+impl Default for FrameQueue {
+    fn default() -> FrameQueue {
+        FrameQueue::new()
+    }
+}
+
+fn is_pixels_on(msg: &Message, channel: u8) -> bool {
+    msg.channel == channel && matches!(msg.command, Command::SetPixelColors { .. })
+}
+
+#[cfg(feature = "tokio")]
+pub use self::sink::Coalesce;
+
+#[cfg(feature = "tokio")]
+mod sink {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures_util::sink::Sink;
+
+    use super::FrameQueue;
+    use crate::Message;
+
+    /// Wraps a `Sink` of messages so it is always ready to accept more,
+    /// coalescing frames in a `FrameQueue` while the inner sink is busy.
+    ///
+    /// Queued frames are moved to the inner sink whenever the wrapper is polled,
+    /// so feed it with `SinkExt::feed` and flush once the render loop can afford to wait.
+    #[derive (Debug)]
+    pub struct Coalesce<S> {
+        inner: S,
+        queue: FrameQueue,
+    }
+
+    impl<S> Coalesce<S> {
+        /// Wrap a sink
+        pub fn new(inner: S) -> Coalesce<S> {
+            Coalesce {
+                inner,
+                queue: FrameQueue::new(),
+            }
+        }
+
+        /// The frames waiting for the inner sink
+        pub fn queue(&self) -> &FrameQueue {
+            &self.queue
+        }
+
+        /// Number of frames replaced before they were sent
+        pub fn dropped(&self) -> u64 {
+            self.queue.dropped()
+        }
+
+        /// Get a reference to the inner sink
+        pub fn get_ref(&self) -> &S {
+            &self.inner
+        }
+
+        /// Unwrap the inner sink, discarding pending messages
+        pub fn into_inner(self) -> S {
+            self.inner
+        }
+    }
+
+    impl<S: Sink<Message> + Unpin> Coalesce<S> {
+        /// Move as many pending messages as the inner sink accepts without waiting
+        fn poll_drain(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+            while !self.queue.is_empty() {
+                match Pin::new(&mut self.inner).poll_ready(cx)? {
+                    Poll::Ready(()) => {
+                        let msg = self.queue.pop().expect("queue is not empty");
+                        Pin::new(&mut self.inner).start_send(msg)?;
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<S: Sink<Message> + Unpin> Sink<Message> for Coalesce<S> {
+        type Error = S::Error;
+
+        fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+            match self.get_mut().poll_drain(cx) {
+                Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+                _ => Poll::Ready(Ok(())),
+            }
+        }
+
+        fn start_send(self: Pin<&mut Self>, msg: Message) -> Result<(), S::Error> {
+            self.get_mut().queue.push(msg);
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+            let this = self.get_mut();
+            futures_util::ready!(this.poll_drain(cx))?;
+            Pin::new(&mut this.inner).poll_flush(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+            let this = self.get_mut();
+            futures_util::ready!(this.poll_drain(cx))?;
+            Pin::new(&mut this.inner).poll_close(cx)
+        }
+    }
+}
+
+#[test]
+fn should_keep_latest_frame_per_channel() {
+
+    let mut queue = FrameQueue::new();
+
+    assert!(!queue.push(Message::from_pixels(1, &[[1; 3]])));
+    assert!(!queue.push(Message::from_data(1, &[0, 1], &[2])));
+    assert!(!queue.push(Message::from_pixels(2, &[[3; 3]])));
+    assert!(queue.push(Message::from_pixels(1, &[[4; 3]])));
+    assert!(queue.push(Message::from_pixels(1, &[[5; 3]])));
+
+    assert_eq!(queue.dropped(), 2);
+    assert_eq!(queue.dropped_on(1), 2);
+    assert_eq!(queue.dropped_on(2), 0);
+    assert!(queue.has_pixels(1));
+
+    let sent: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+    assert_eq!(sent, vec![
+        Message::from_data(1, &[0, 1], &[2]),
+        Message::from_pixels(2, &[[3; 3]]),
+        Message::from_pixels(1, &[[5; 3]]),
+    ]);
+
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn should_coalesce_while_inner_sink_is_busy() {
+    use futures_util::{SinkExt, StreamExt};
+
+    // A sink that holds a single message until it is received
+    let (tx, rx) = futures::channel::mpsc::channel(0);
+    let mut sink = Coalesce::new(tx);
+
+    for i in 0..10 {
+        sink.feed(Message::from_pixels(1, &[[i; 3]])).await.unwrap();
+    }
+    assert_eq!(sink.dropped(), 8);
+
+    let receiver = tokio::spawn(rx.collect::<Vec<_>>());
+    sink.flush().await.unwrap();
+    drop(sink);
+
+    assert_eq!(receiver.await.unwrap(), vec![
+        Message::from_pixels(1, &[[0; 3]]),
+        Message::from_pixels(1, &[[9; 3]]),
+    ]);
+
+}
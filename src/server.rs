@@ -0,0 +1,467 @@
+//! Async OPC server that dispatches decoded messages to a `Handler`.
+//!
+//! ```rust,no_run
+//! use opc::server::{Handler, Server};
+//!
+//! struct Print;
+//!
+//! impl Handler for Print {
+//!     fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) {
+//!         println!("channel {}: {} pixels", channel, pixels.len());
+//!     }
+//! }
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     Server::new(Print).channels(1..=8).listen("127.0.0.1:7890").await.unwrap();
+//! }
+//! ```
+
+use std::convert::TryInto;
+use std::io;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::net::{TcpListener, ToSocketAddrs, UdpSocket};
+
+use crate::{as_pixels, Address, ColorOrder, ColorOrders, Command, Message, OpcCodec, OpcError};
+use crate::{ParseAddressError, UnknownCommandPolicy};
+
+/// Largest datagram the UDP listener accepts, the most a UDP payload can hold.
+const MAX_DATAGRAM: usize = 65535;
+
+/// How long to stop accepting after an error that is not about a single connection, such as running out of file descriptors.
+const ACCEPT_PAUSE: Duration = Duration::from_millis(100);
+
+/// Receives the messages decoded by a `Server`.
+///
+/// Broadcast messages on channel 0 are delivered once for every channel the server registered,
+/// so a handler only sees the channel 0 itself when no channels are registered.
+pub trait Handler: Send + 'static {
+    /// New values for the first pixels of a channel
+    fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]);
+
+    /// The raw data of a Set Pixel Colors message, for handlers decoding another `PixelFormat`
+    ///
+    /// Forwards the complete RGB pixels to `on_pixels` by default.
+    fn on_pixel_data(&mut self, channel: u8, data: &[u8]) {
+        self.on_pixels(channel, as_pixels(data));
+    }
+
+    /// A System Exclusive message, which a `SysExRegistry` can turn into a typed payload
+    fn on_sysex(&mut self, _channel: u8, _id: [u8; 2], _data: &[u8]) {}
+
+    /// A message with a command this crate does not interpret, if the codec passes them through
+    fn on_unknown(&mut self, _channel: u8, _command: u8, _data: &[u8]) {}
+
+    /// A frame that failed to decode, or a connection that failed
+    ///
+    /// The server skips bad frames and keeps serving, so this is only for logging.
+    fn on_error(&mut self, _err: &OpcError) {}
+}
+
+/// Async OPC Server Instance
+///
+/// Every connection is decoded on its own task, all of them sharing one handler.
+#[derive (Debug)]
+pub struct Server<H> {
+    handler: Arc<Mutex<H>>,
+    channels: Arc<[u8]>,
+    orders: Arc<ColorOrders>,
+    codec: OpcCodec,
+}
+
+impl<H> Clone for Server<H> {
+    fn clone(&self) -> Server<H> {
+        Server {
+            handler: self.handler.clone(),
+            channels: self.channels.clone(),
+            orders: self.orders.clone(),
+            codec: self.codec.clone(),
+        }
+    }
+}
+
+impl<H: Handler> Server<H> {
+    /// Create new Server Instance delivering every channel to `handler`
+    ///
+    /// Frames with unknown commands are skipped, set a codec that passes them through to see them.
+    pub fn new(handler: H) -> Server<H> {
+        Server {
+            handler: Arc::new(Mutex::new(handler)),
+            channels: Arc::new([]),
+            orders: Arc::new(ColorOrders::new()),
+            codec: OpcCodec::new().unknown_command_policy(UnknownCommandPolicy::Skip),
+        }
+    }
+
+    /// Only deliver messages for these channels, fanning broadcast messages out to each of them
+    pub fn channels<I: IntoIterator<Item = u8>>(mut self, channels: I) -> Server<H> {
+        let mut channels: Vec<u8> = channels.into_iter().filter(|&ch| ch != crate::BROADCAST_CHANNEL).collect();
+        channels.sort_unstable();
+        channels.dedup();
+        self.channels = channels.into();
+        self
+    }
+
+    /// Reorder the pixels of every channel from its strip into RGB before delivering them
+    ///
+    /// Broadcast messages are reordered for each registered channel they fan out to.
+    pub fn color_orders(mut self, orders: ColorOrders) -> Server<H> {
+        self.orders = Arc::new(orders);
+        self
+    }
+
+    /// Use a custom codec to decode messages
+    pub fn codec(mut self, codec: OpcCodec) -> Server<H> {
+        self.codec = codec;
+        self
+    }
+
+    /// The shared handler
+    pub fn handler(&self) -> &Arc<Mutex<H>> {
+        &self.handler
+    }
+
+    /// Deliver a message to the handler
+    ///
+    /// A handler that panicked while handling an earlier message keeps receiving messages.
+    pub fn dispatch(&self, msg: &Message) {
+        let mut handler = self.handler.lock().unwrap_or_else(PoisonError::into_inner);
+        let targets: &[u8] = if self.channels.is_empty() {
+            &[]
+        } else if msg.is_broadcast() {
+            &self.channels
+        } else if self.channels.binary_search(&msg.channel).is_ok() {
+            std::slice::from_ref(&msg.channel)
+        } else {
+            return;
+        };
+
+        if targets.is_empty() {
+            deliver(&mut *handler, msg.channel, &msg.command, self.orders.get(msg.channel));
+        }
+        for &channel in targets {
+            deliver(&mut *handler, channel, &msg.command, self.orders.get(channel));
+        }
+    }
+
+    /// Report an error to the handler
+    pub(crate) fn report(&self, err: &OpcError) {
+        self.handler.lock().unwrap_or_else(PoisonError::into_inner).on_error(err);
+    }
+
+    /// Deliver every frame of a self-contained buffer, such as a datagram
+    ///
+    /// Malformed frames are reported and dropped, and so is an incomplete frame at the end.
+    pub(crate) fn dispatch_frames(&self, mut buf: BytesMut) {
+        loop {
+            match self.codec.decode_complete(&mut buf) {
+                Ok(Some(msg)) => self.dispatch(&msg),
+                Ok(None) => break,
+                Err(err) => self.report(&err),
+            }
+        }
+        if !buf.is_empty() {
+            self.report(&OpcError::Truncated);
+        }
+    }
+
+    /// Deliver the complete frames at the start of a stream's buffer, reporting the ones that fail to decode
+    fn dispatch_decoded(&self, buf: &mut BytesMut) {
+        loop {
+            match self.codec.decode_from(buf) {
+                Ok(Some(msg)) => self.dispatch(&msg),
+                Ok(None) => return,
+                // The bad frame is consumed already, carry on with the next one
+                Err(err) => self.report(&err),
+            }
+        }
+    }
+
+    /// Decode messages from a stream until it closes
+    ///
+    /// Frames that fail to decode are reported to the handler and skipped, only transport errors end the connection.
+    pub async fn serve_connection<S: AsyncRead + Unpin>(&self, mut stream: S) -> Result<(), OpcError> {
+        let mut buf = BytesMut::new();
+        loop {
+            self.dispatch_decoded(&mut buf);
+            buf.reserve(4096);
+            if stream.read_buf(&mut buf).await? == 0 {
+                if !buf.is_empty() {
+                    self.report(&OpcError::Truncated);
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    /// Serve a connection on its own task, reporting the error that ends it
+    fn spawn_connection<S: AsyncRead + Unpin + Send + 'static>(&self, socket: S) {
+        let server = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = server.serve_connection(socket).await {
+                server.report(&err);
+            }
+        });
+    }
+
+    /// Report an accept error, pausing unless it only concerns the connection being accepted
+    pub(crate) async fn accept_failed(&self, err: io::Error) {
+        let pause = !matches!(err.kind(),
+                              io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused);
+        self.report(&err.into());
+        if pause {
+            tokio::time::sleep(ACCEPT_PAUSE).await;
+        }
+    }
+
+    /// Accept connections and serve each on its own task
+    ///
+    /// Accept errors are reported to the handler and accepting carries on.
+    pub async fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => self.spawn_connection(socket),
+                Err(err) => self.accept_failed(err).await,
+            }
+        }
+    }
+
+    /// Bind to `addr` and serve incoming connections
+    pub async fn listen<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        self.serve(TcpListener::bind(addr).await?).await
+    }
+
+    /// Accept Unix domain socket connections and serve each on its own task
+    #[cfg(unix)]
+    pub async fn serve_unix(&self, listener: UnixListener) -> io::Result<()> {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => self.spawn_connection(socket),
+                Err(err) => self.accept_failed(err).await,
+            }
+        }
+    }
+
+    /// Bind a Unix domain socket at `path` and serve incoming connections
+    ///
+    /// Binding fails if the path exists, so remove stale sockets first.
+    #[cfg(unix)]
+    pub async fn listen_unix<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.serve_unix(UnixListener::bind(path)?).await
+    }
+
+    /// Listen at a `tcp://` or `unix://` address and serve incoming connections
+    ///
+    /// Other schemes fail with `io::ErrorKind::InvalidInput`.
+    pub async fn listen_address<A>(&self, addr: A) -> io::Result<()>
+        where A: TryInto<Address>,
+              A::Error: Into<ParseAddressError>
+    {
+        let addr = addr.try_into().map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.into()))?;
+        match addr {
+            Address::Tcp(addr) => self.listen(addr).await,
+            #[cfg(unix)]
+            Address::Unix(path) => self.listen_unix(path).await,
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "Unix domain sockets are not supported")),
+        }
+    }
+
+    /// Decode the frames of every datagram received on `socket`
+    ///
+    /// Malformed frames are reported and dropped, and so is the incomplete end of a datagram.
+    pub async fn serve_udp(&self, socket: UdpSocket) -> io::Result<()> {
+        let mut datagram = vec![0; MAX_DATAGRAM];
+        loop {
+            let (len, _) = socket.recv_from(&mut datagram).await?;
+            self.dispatch_frames(BytesMut::from(&datagram[..len]));
+        }
+    }
+
+    /// Bind a UDP socket to `addr` and serve the datagrams it receives
+    pub async fn listen_udp<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        self.serve_udp(UdpSocket::bind(addr).await?).await
+    }
+}
+
+fn deliver<H: Handler>(handler: &mut H, channel: u8, command: &Command, order: ColorOrder) {
+    match *command {
+        Command::SetPixelColors { ref pixels } if order != ColorOrder::Rgb => {
+            handler.on_pixel_data(channel, &order.from_wire_data(pixels.as_bytes()))
+        }
+        Command::SetPixelColors { ref pixels } => handler.on_pixel_data(channel, pixels.as_bytes()),
+        Command::SystemExclusive { id, ref data } => handler.on_sysex(channel, id, data),
+        Command::Unknown { command, ref data } => handler.on_unknown(channel, command, data),
+    }
+}
+
+#[cfg(test)]
+struct Record(tokio::sync::mpsc::UnboundedSender<Message>);
+
+#[cfg(test)]
+impl Handler for Record {
+    fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) {
+        self.0.send(Message::from_pixels(channel, pixels)).unwrap();
+    }
+
+    fn on_sysex(&mut self, channel: u8, id: [u8; 2], data: &[u8]) {
+        self.0.send(Message::from_data(channel, &id, data)).unwrap();
+    }
+}
+
+#[test]
+fn should_fan_out_broadcast_to_registered_channels() {
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let server = Server::new(Record(tx)).channels(vec![3, 1, 0, 3]);
+
+    server.dispatch(&Message::from_pixels(0, &[[1, 2, 3]]));
+    server.dispatch(&Message::from_pixels(3, &[[4, 5, 6]]));
+    server.dispatch(&Message::from_pixels(2, &[[7, 8, 9]]));
+
+    assert_eq!(rx.try_recv().unwrap(), Message::from_pixels(1, &[[1, 2, 3]]));
+    assert_eq!(rx.try_recv().unwrap(), Message::from_pixels(3, &[[1, 2, 3]]));
+    assert_eq!(rx.try_recv().unwrap(), Message::from_pixels(3, &[[4, 5, 6]]));
+    assert!(rx.try_recv().is_err());
+
+}
+
+#[test]
+fn should_keep_dispatching_after_a_handler_panics() {
+
+    struct PanicOnce(tokio::sync::mpsc::UnboundedSender<Message>, bool);
+
+    impl Handler for PanicOnce {
+        fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) {
+            if !std::mem::replace(&mut self.1, true) {
+                panic!("first frame");
+            }
+            self.0.send(Message::from_pixels(channel, pixels)).unwrap();
+        }
+    }
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let server = Server::new(PanicOnce(tx, false));
+
+    let msg = Message::from_pixels(1, &[[1, 2, 3]]);
+    assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| server.dispatch(&msg))).is_err());
+    server.dispatch(&msg);
+
+    assert_eq!(rx.try_recv().unwrap(), msg);
+
+}
+
+#[test]
+fn should_reorder_colors_per_channel() {
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let orders = ColorOrders::new().channel(1, ColorOrder::Grb).channel(2, ColorOrder::Bgr);
+    let server = Server::new(Record(tx)).channels(1..=3).color_orders(orders);
+
+    server.dispatch(&Message::from_pixels(0, &[[1, 2, 3]]));
+
+    assert_eq!(rx.try_recv().unwrap(), Message::from_pixels(1, &[[2, 1, 3]]));
+    assert_eq!(rx.try_recv().unwrap(), Message::from_pixels(2, &[[3, 2, 1]]));
+    assert_eq!(rx.try_recv().unwrap(), Message::from_pixels(3, &[[1, 2, 3]]));
+
+}
+
+#[tokio::test]
+async fn should_serve_tcp_connections() {
+    use futures_util::SinkExt;
+    use tokio::net::TcpStream;
+    use tokio_util::codec::Framed;
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(Record(tx));
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let mut client = Framed::new(TcpStream::connect(addr).await.unwrap(), OpcCodec::new());
+    client.send(Message::from_pixels(0, &[[1, 2, 3]])).await.unwrap();
+    client.send(Message::from_data(2, &[0, 1], &[4])).await.unwrap();
+
+    assert_eq!(rx.recv().await.unwrap(), Message::from_pixels(0, &[[1, 2, 3]]));
+    assert_eq!(rx.recv().await.unwrap(), Message::from_data(2, &[0, 1], &[4]));
+
+}
+
+#[tokio::test]
+async fn should_report_bad_frames_and_keep_decoding() {
+
+    struct Errors(Vec<String>, Vec<Message>);
+
+    impl Handler for Errors {
+        fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) {
+            self.1.push(Message::from_pixels(channel, pixels));
+        }
+
+        fn on_error(&mut self, err: &OpcError) {
+            self.0.push(err.to_string());
+        }
+    }
+
+    let server = Server::new(Errors(Vec::new(), Vec::new()));
+    // A SysEx without a system ID, an unknown command, a good frame and a truncated one
+    let stream: &[u8] = &[1, 0xff, 0, 1, 0, 2, 0x42, 0, 1, 9, 3, 0, 0, 3, 1, 2, 3, 4, 0, 0, 3, 1];
+
+    server.serve_connection(stream).await.unwrap();
+
+    let handler = server.handler().lock().unwrap();
+    assert_eq!(handler.0, [OpcError::SysExTooShort.to_string(), OpcError::Truncated.to_string()]);
+    assert_eq!(handler.1, [Message::from_pixels(3, &[[1, 2, 3]])]);
+
+}
+
+#[tokio::test]
+async fn should_feed_datagrams_to_server() {
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let server = Server::new(Record(tx));
+    tokio::spawn(async move { server.serve_udp(socket).await });
+
+    let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    // A malformed frame followed by a good one, then a truncated frame
+    sender.send_to(&[1, 0xff, 0, 1, 0, 2, 0, 0, 3, 4, 5, 6], addr).await.unwrap();
+    sender.send_to(&[3, 0, 0, 6, 7, 8, 9], addr).await.unwrap();
+    sender.send_to(&[4, 0, 0, 3, 1, 1, 1], addr).await.unwrap();
+
+    assert_eq!(rx.recv().await.unwrap(), Message::from_pixels(2, &[[4, 5, 6]]));
+    assert_eq!(rx.recv().await.unwrap(), Message::from_pixels(4, &[[1, 1, 1]]));
+
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn should_serve_unix_socket_clients() {
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let dir = std::env::temp_dir().join(format!("opc-server-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let addr = format!("unix://{}", dir.join("opc.sock").display());
+    let _ = std::fs::remove_file(dir.join("opc.sock"));
+
+    let server = Server::new(Record(tx));
+    tokio::spawn({
+        let addr = addr.clone();
+        async move { server.listen_address(addr).await }
+    });
+
+    let client = crate::Client::connect(addr);
+    client.set_pixels(1, &[[1, 2, 3]]).unwrap();
+
+    assert_eq!(rx.recv().await.unwrap(), Message::from_pixels(1, &[[1, 2, 3]]));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+}
@@ -0,0 +1,168 @@
+//! Typed System Exclusive payloads, keyed by their two-byte system ID.
+//!
+//! Vendors implement `SysExPayload` for their messages, and a `SysExRegistry`
+//! turns the data of a System Exclusive message into whichever payload was registered for its system ID.
+//! Messages with an unregistered ID are handed back untouched, so they can still be forwarded:
+//!
+//! ```rust
+//! # #[cfg(feature = "fadecandy")] {
+//! use opc::fadecandy::{self, ColorCorrection};
+//! use opc::sysex::{Decoded, SysExRegistry};
+//!
+//! let registry = SysExRegistry::<fadecandy::SysEx>::new().register::<fadecandy::SysEx>();
+//! let msg = ColorCorrection::default().to_message(0);
+//!
+//! match registry.decode_message(&msg).unwrap() {
+//!     Some(Decoded::Known(fadecandy::SysEx::ColorCorrection(correction))) => println!("{:?}", correction),
+//!     Some(Decoded::Known(other)) => println!("{:?}", other),
+//!     Some(Decoded::Unknown { id, .. }) => println!("forwarding system {:?}", id),
+//!     None => println!("not a system exclusive message"),
+//! }
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{Command, Message, OpcError};
+
+/// A System Exclusive payload belonging to a single system ID.
+pub trait SysExPayload: Sized {
+    /// The two-byte system ID the payload is sent under.
+    const SYSTEM_ID: [u8; 2];
+
+    /// Encode as the data following the system ID
+    fn encode(&self) -> Vec<u8>;
+
+    /// Parse the data following the system ID
+    fn decode(data: &[u8]) -> Result<Self, OpcError>;
+
+    /// Create new System Exclusive Message for `channel`
+    fn to_message(&self, channel: u8) -> Message {
+        Message::from_data(channel, &Self::SYSTEM_ID, &self.encode())
+    }
+
+    /// Parse a System Exclusive Message
+    fn from_message(msg: &Message) -> Result<Self, OpcError> {
+        match msg.command {
+            Command::SystemExclusive { id, ref data } if id == Self::SYSTEM_ID => Self::decode(data),
+            _ => Err(OpcError::InvalidSysEx(format!("not a system exclusive message for system {:02x}{:02x}",
+                                                    Self::SYSTEM_ID[0],
+                                                    Self::SYSTEM_ID[1]))),
+        }
+    }
+}
+
+/// The result of decoding System Exclusive data with a `SysExRegistry`.
+#[derive (Clone, Debug, PartialEq)]
+pub enum Decoded<'a, T> {
+    /// The payload registered for the system ID.
+    Known(T),
+    /// No payload is registered for the system ID.
+    Unknown {
+        /// The two-byte system ID.
+        id: [u8; 2],
+        /// The data following the system ID.
+        data: &'a [u8],
+    },
+}
+
+type Decoder<T> = fn(&[u8]) -> Result<T, OpcError>;
+
+/// Decodes System Exclusive data into `T`, using the payload registered for each system ID.
+///
+/// `T` is usually an application enum with a variant for every vendor it understands.
+pub struct SysExRegistry<T> {
+    decoders: HashMap<[u8; 2], Decoder<T>>,
+}
+
+impl<T> SysExRegistry<T> {
+    /// Create new empty Registry
+    pub fn new() -> SysExRegistry<T> {
+        SysExRegistry { decoders: HashMap::new() }
+    }
+
+    /// Decode the data of payload `P`'s system ID as `P`, replacing any payload registered for it before
+    pub fn register<P: SysExPayload + Into<T>>(mut self) -> SysExRegistry<T> {
+        self.decoders.insert(P::SYSTEM_ID, |data| P::decode(data).map(Into::into));
+        self
+    }
+
+    /// Check if a payload is registered for a system ID
+    pub fn contains(&self, id: [u8; 2]) -> bool {
+        self.decoders.contains_key(&id)
+    }
+
+    /// Decode the data following a system ID
+    pub fn decode<'a>(&self, id: [u8; 2], data: &'a [u8]) -> Result<Decoded<'a, T>, OpcError> {
+        match self.decoders.get(&id) {
+            Some(decode) => decode(data).map(Decoded::Known),
+            None => Ok(Decoded::Unknown { id, data }),
+        }
+    }
+
+    /// Decode a message, returning `None` if it is not a System Exclusive message
+    pub fn decode_message<'a>(&self, msg: &'a Message) -> Result<Option<Decoded<'a, T>>, OpcError> {
+        match msg.command {
+            Command::SystemExclusive { id, ref data } => self.decode(id, data).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<T> Default for SysExRegistry<T> {
+    fn default() -> SysExRegistry<T> {
+        SysExRegistry::new()
+    }
+}
+
+impl<T> fmt::Debug for SysExRegistry<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.decoders.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+#[derive (Debug, PartialEq)]
+struct Ping(u8);
+
+#[cfg(test)]
+impl SysExPayload for Ping {
+    const SYSTEM_ID: [u8; 2] = [0x7f, 0x01];
+
+    fn encode(&self) -> Vec<u8> {
+        vec![self.0]
+    }
+
+    fn decode(data: &[u8]) -> Result<Ping, OpcError> {
+        match *data {
+            [value] => Ok(Ping(value)),
+            _ => Err(OpcError::InvalidSysEx("ping holds a single byte".to_string())),
+        }
+    }
+}
+
+#[test]
+fn should_decode_registered_payloads() {
+
+    let registry = SysExRegistry::<Ping>::new().register::<Ping>();
+    let msg = Ping(7).to_message(3);
+
+    assert_eq!(msg, Message::from_data(3, &[0x7f, 0x01], &[7]));
+    assert_eq!(registry.decode_message(&msg).unwrap(), Some(Decoded::Known(Ping(7))));
+    assert!(registry.decode([0x7f, 0x01], &[1, 2]).is_err());
+
+}
+
+#[test]
+fn should_pass_through_unknown_ids() {
+
+    let registry = SysExRegistry::<Ping>::new().register::<Ping>();
+    let msg = Message::from_data(3, &[0x12, 0x34], &[5, 6]);
+
+    assert_eq!(registry.decode_message(&msg).unwrap(),
+               Some(Decoded::Unknown { id: [0x12, 0x34], data: &[5, 6] }));
+    assert_eq!(registry.decode_message(&Message::from_pixels(1, &[])).unwrap(), None);
+    assert!(!registry.contains([0x12, 0x34]));
+
+}
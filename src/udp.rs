@@ -0,0 +1,150 @@
+//! OPC over UDP, one frame per datagram.
+//!
+//! Datagrams carry frames with the same header `OpcCodec` writes, but a lost or late frame
+//! never holds up the ones after it, which suits controllers on lossy Wi-Fi.
+//! A frame has to fit in a single datagram, so the client rejects larger frames with
+//! `OpcError::DatagramTooLarge` instead of sending something the network would fragment or drop.
+//!
+//! ```rust,no_run
+//! use opc::udp::Client;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let mut client = Client::connect("192.168.1.230:7890").await.unwrap();
+//!     client.set_pixels(1, &[[255, 0, 0]; 100]).await.unwrap();
+//! }
+//! ```
+//!
+//! On the receiving side, `Server::listen_udp` feeds datagrams to the same handler as TCP connections.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::net::{lookup_host, ToSocketAddrs, UdpSocket};
+
+use crate::parser::frame_len;
+use crate::{ColorOrders, MessageRef, OpcCodec, OpcError};
+
+/// Largest UDP payload that fits a 1500 byte Ethernet frame without IP fragmentation.
+pub const DEFAULT_MAX_DATAGRAM: usize = 1472;
+
+/// UDP OPC Client Instance
+#[derive (Debug)]
+pub struct Client {
+    socket: UdpSocket,
+    codec: OpcCodec,
+    orders: ColorOrders,
+    max_datagram: usize,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    /// Bind a local socket and send to the server at `addr`
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Client> {
+        let addr = match lookup_host(addr).await?.next() {
+            Some(addr) => addr,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no address to send to")),
+        };
+        let local: SocketAddr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        Ok(Client::from_socket(socket))
+    }
+
+    /// Send through a socket that is already connected to the server
+    pub fn from_socket(socket: UdpSocket) -> Client {
+        Client {
+            socket,
+            codec: OpcCodec::new(),
+            orders: ColorOrders::new(),
+            max_datagram: DEFAULT_MAX_DATAGRAM,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Use a custom codec to encode messages
+    pub fn with_codec(mut self, codec: OpcCodec) -> Client {
+        self.codec = codec;
+        self
+    }
+
+    /// Reorder the pixels of every channel for its strip before encoding
+    pub fn with_color_orders(mut self, orders: ColorOrders) -> Client {
+        self.orders = orders;
+        self
+    }
+
+    /// Set the largest datagram to send, for paths with an MTU other than Ethernet's
+    pub fn with_max_datagram(mut self, max_datagram: usize) -> Client {
+        self.max_datagram = max_datagram;
+        self
+    }
+
+    /// The local socket
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Send a message, either a `&Message` or a borrowed `MessageRef`
+    ///
+    /// Nothing is sent if any of the message's frames is too large for a datagram.
+    pub async fn send<'a, M: Into<MessageRef<'a>>>(&mut self, msg: M) -> Result<(), OpcError> {
+        self.buf.clear();
+        self.orders.encode_into(&self.codec, msg.into(), &mut self.buf)?;
+
+        let mut frames = Vec::new();
+        let mut rest = &self.buf[..];
+        while let Some(len) = frame_len(rest) {
+            if len > self.max_datagram {
+                return Err(OpcError::DatagramTooLarge(len));
+            }
+            let (frame, next) = rest.split_at(len);
+            frames.push(frame);
+            rest = next;
+        }
+
+        for frame in frames {
+            self.socket.send(frame).await?;
+        }
+        Ok(())
+    }
+
+    /// Set the first pixels of a channel
+    pub async fn set_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) -> Result<(), OpcError> {
+        self.send(MessageRef::from_pixels(channel, pixels)).await
+    }
+
+    /// Send a System Exclusive message
+    pub async fn sysex(&mut self, channel: u8, id: [u8; 2], data: &[u8]) -> Result<(), OpcError> {
+        self.send(MessageRef::from_data(channel, &id, data)).await
+    }
+}
+
+#[tokio::test]
+async fn should_send_one_frame_per_datagram() {
+    use crate::{ColorOrder, Message};
+
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut client = Client::connect(server.local_addr().unwrap())
+        .await
+        .unwrap()
+        .with_color_orders(ColorOrders::new().channel(3, ColorOrder::Grb));
+
+    client.set_pixels(1, &[[1, 2, 3]]).await.unwrap();
+    client.sysex(2, [0, 1], &[4]).await.unwrap();
+    client.set_pixels(3, &[[1, 2, 3]]).await.unwrap();
+
+    let mut buf = [0; 64];
+    let len = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..len], &[1, 0, 0, 3, 1, 2, 3]);
+    let len = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..len], &[2, 0xff, 0, 3, 0, 1, 4]);
+    let len = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..len], &[3, 0, 0, 3, 2, 1, 3]);
+
+    match client.send(&Message::from_pixels(1, &[[0; 3]; 500])).await {
+        Err(OpcError::DatagramTooLarge(1504)) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+}
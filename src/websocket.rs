@@ -0,0 +1,459 @@
+//! OPC over WebSockets, compatible with Fadecandy's fcserver.
+//!
+//! Like fcserver, binary WebSocket messages carry OPC frames with the header `OpcCodec` writes,
+//! and text messages carry JSON `Control` messages such as `list_connected_devices`.
+//! Each binary message holds exactly one frame, whose length is taken from the message,
+//! so browser clients that leave the header's length field zero work too.
+//!
+//! ```rust,no_run
+//! use opc::websocket::Client;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let mut client = Client::connect("ws://127.0.0.1:7890").await.unwrap();
+//!
+//!     for device in client.list_connected_devices().await.unwrap() {
+//!         println!("{} {:?}", device.kind, device.serial);
+//!     }
+//!     client.set_pixels(0, &[[255, 0, 0]; 64]).await.unwrap();
+//! }
+//! ```
+//!
+//! On the receiving side, `Server::listen_websocket` feeds binary messages to the same handler as TCP connections
+//! and answers control messages from a shared `Devices` list.
+
+use std::convert::TryFrom;
+use std::io;
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::{self, Message as WsMessage};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::parser::{frame_len, HEADER_LEN};
+use crate::server::{Handler, Server};
+use crate::{ColorOrders, Message, MessageRef, OpcCodec, OpcError, Pixels};
+
+/// A device as fcserver describes it, such as `{"type": "fadecandy", "serial": "FFFFFFFFFFFF00180017200214134D44"}`.
+#[derive (Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    /// The kind of device, `fadecandy` or `enttec`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The device's serial number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    /// Any other properties, such as `version` and `timestamp`.
+    #[serde(flatten)]
+    pub properties: serde_json::Map<String, serde_json::Value>,
+    /// OPC channel a server delivers the device's `device_pixels` messages on. Not sent to clients.
+    #[serde(skip)]
+    pub channel: Option<u8>,
+}
+
+impl Device {
+    /// Create new Device of a kind with a serial number
+    pub fn new<K: Into<String>, S: Into<String>>(kind: K, serial: S) -> Device {
+        Device {
+            kind: kind.into(),
+            serial: Some(serial.into()),
+            properties: serde_json::Map::new(),
+            channel: None,
+        }
+    }
+
+    /// Deliver the device's pixels on an OPC channel
+    pub fn channel(mut self, channel: u8) -> Device {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// Check if both describe the same physical device
+    pub fn is_same(&self, other: &Device) -> bool {
+        self.kind == other.kind && self.serial == other.serial
+    }
+}
+
+/// fcserver's JSON control messages, tagged by their `type`.
+///
+/// Requests may carry a `sequence` number, which the reply repeats.
+#[derive (Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Control {
+    /// Ask for the connected devices, or the reply listing them.
+    ListConnectedDevices {
+        /// Sequence number of the request.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sequence: Option<u64>,
+        /// The connected devices, only set in the reply.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        devices: Option<Vec<Device>>,
+    },
+    /// Sent by the server whenever a device is connected or disconnected.
+    ConnectedDevicesChanged {
+        /// The connected devices.
+        devices: Vec<Device>,
+    },
+    /// Set the pixels of a single device, bypassing the OPC channel mapping, or the reply to it.
+    DevicePixels {
+        /// Sequence number of the request.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sequence: Option<u64>,
+        /// The device to set, only set in the request.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        device: Option<Device>,
+        /// Pixels in red, green, blue order, only set in the request.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pixels: Vec<u8>,
+    },
+}
+
+/// The devices a WebSocket server reports, shared by all of its connections.
+///
+/// Connections are sent `connected_devices_changed` whenever the list is replaced.
+#[derive (Clone, Debug)]
+pub struct Devices {
+    devices: Arc<watch::Sender<Vec<Device>>>,
+}
+
+impl Devices {
+    /// Create new empty Device List
+    pub fn new() -> Devices {
+        Devices { devices: Arc::new(watch::Sender::new(Vec::new())) }
+    }
+
+    /// Replace the connected devices
+    pub fn set(&self, devices: Vec<Device>) {
+        self.devices.send_replace(devices);
+    }
+
+    /// The connected devices
+    pub fn get(&self) -> Vec<Device> {
+        self.devices.borrow().clone()
+    }
+}
+
+impl Default for Devices {
+    fn default() -> Devices {
+        Devices::new()
+    }
+}
+
+/// A binary message as one frame, with the header's length field replaced by the message's length
+///
+/// Messages too short for a header are `Truncated`, messages too long for a frame are `MessageTooLarge`.
+fn frame_from_message(data: &[u8]) -> Result<BytesMut, OpcError> {
+    let len = data.len().checked_sub(HEADER_LEN).ok_or(OpcError::Truncated)?;
+    let len = u16::try_from(len).map_err(|_| OpcError::MessageTooLarge(len))?;
+    let mut frame = BytesMut::from(data);
+    frame[2..HEADER_LEN].copy_from_slice(&len.to_be_bytes());
+    Ok(frame)
+}
+
+/// WebSocket OPC Client Instance
+#[derive (Debug)]
+pub struct Client {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    codec: OpcCodec,
+    orders: ColorOrders,
+    buf: Vec<u8>,
+    sequence: u64,
+}
+
+impl Client {
+    /// Connect to a server at a `ws://` URL
+    pub async fn connect(url: &str) -> Result<Client, OpcError> {
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.map_err(ws_error)?;
+        Ok(Client {
+            ws,
+            codec: OpcCodec::new(),
+            orders: ColorOrders::new(),
+            buf: Vec::new(),
+            sequence: 0,
+        })
+    }
+
+    /// Use a custom codec to encode messages
+    pub fn with_codec(mut self, codec: OpcCodec) -> Client {
+        self.codec = codec;
+        self
+    }
+
+    /// Reorder the pixels of every channel for its strip before encoding
+    pub fn with_color_orders(mut self, orders: ColorOrders) -> Client {
+        self.orders = orders;
+        self
+    }
+
+    /// Send a message, either a `&Message` or a borrowed `MessageRef`, one frame per binary message
+    pub async fn send<'a, M: Into<MessageRef<'a>>>(&mut self, msg: M) -> Result<(), OpcError> {
+        self.buf.clear();
+        self.orders.encode_into(&self.codec, msg.into(), &mut self.buf)?;
+
+        let mut rest = &self.buf[..];
+        while let Some(len) = frame_len(rest) {
+            let (frame, next) = rest.split_at(len);
+            self.ws.feed(WsMessage::binary(frame.to_vec())).await.map_err(ws_error)?;
+            rest = next;
+        }
+        self.ws.flush().await.map_err(ws_error)
+    }
+
+    /// Set the first pixels of a channel
+    pub async fn set_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) -> Result<(), OpcError> {
+        self.send(MessageRef::from_pixels(channel, pixels)).await
+    }
+
+    /// Send a control message
+    pub async fn send_control(&mut self, control: &Control) -> Result<(), OpcError> {
+        let json = serde_json::to_string(control).map_err(io::Error::from)?;
+        self.ws.send(WsMessage::text(json)).await.map_err(ws_error)
+    }
+
+    /// Wait for the next control message, skipping any other messages
+    ///
+    /// Returns `None` once the server closes the connection.
+    pub async fn next_control(&mut self) -> Option<Result<Control, OpcError>> {
+        while let Some(msg) = self.ws.next().await {
+            match msg {
+                Ok(WsMessage::Text(text)) => {
+                    return Some(serde_json::from_str(text.as_str()).map_err(|err| io::Error::from(err).into()));
+                }
+                Ok(_) => continue,
+                Err(err) => return Some(Err(ws_error(err))),
+            }
+        }
+        None
+    }
+
+    /// Ask for the connected devices and wait for the reply
+    ///
+    /// Control messages that arrive before the reply are dropped.
+    pub async fn list_connected_devices(&mut self) -> Result<Vec<Device>, OpcError> {
+        self.sequence += 1;
+        let sequence = Some(self.sequence);
+        self.send_control(&Control::ListConnectedDevices { sequence, devices: None }).await?;
+
+        loop {
+            match self.next_control().await {
+                Some(Ok(Control::ListConnectedDevices { sequence: reply, devices })) if reply == sequence => {
+                    return Ok(devices.unwrap_or_default());
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => return Err(err),
+                None => return Err(io::Error::from(io::ErrorKind::ConnectionReset).into()),
+            }
+        }
+    }
+
+    /// Set the pixels of a single device, without waiting for the reply
+    pub async fn device_pixels(&mut self, device: &Device, pixels: &[[u8; 3]]) -> Result<(), OpcError> {
+        self.sequence += 1;
+        self.send_control(&Control::DevicePixels {
+                sequence: Some(self.sequence),
+                device: Some(device.clone()),
+                pixels: pixels.as_flattened().to_vec(),
+            })
+            .await
+    }
+}
+
+impl<H: Handler> Server<H> {
+    /// Perform the WebSocket handshake on a stream and serve it until it closes
+    ///
+    /// Binary messages are decoded as OPC frames. `list_connected_devices` is answered from `devices`,
+    /// and `device_pixels` is delivered as Set Pixel Colors on the device's channel, if it has one.
+    pub async fn serve_websocket<S>(&self, stream: S, devices: &Devices) -> Result<(), OpcError>
+        where S: AsyncRead + AsyncWrite + Unpin
+    {
+        let mut ws = tokio_tungstenite::accept_async(stream).await.map_err(ws_error)?;
+        let mut changes = devices.devices.subscribe();
+
+        loop {
+            let reply = tokio::select! {
+                msg = ws.next() => match msg {
+                    Some(Ok(WsMessage::Binary(data))) => {
+                        match frame_from_message(&data) {
+                            Ok(frame) => self.dispatch_frames(frame),
+                            Err(err) => self.report(&err),
+                        }
+                        None
+                    }
+                    Some(Ok(WsMessage::Text(text))) => match serde_json::from_str(text.as_str()) {
+                        Ok(control) => Some(self.control(control, devices)),
+                        // Like fcserver, ignore what cannot be understood
+                        Err(_) => None,
+                    },
+                    Some(Ok(WsMessage::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => None,
+                    Some(Err(err)) => return Err(ws_error(err)),
+                },
+                changed = changes.changed() => match changed {
+                    Ok(()) => Some(Control::ConnectedDevicesChanged { devices: changes.borrow_and_update().clone() }),
+                    Err(_) => None,
+                },
+            };
+
+            if let Some(reply) = reply {
+                let json = serde_json::to_string(&reply).map_err(io::Error::from)?;
+                ws.send(WsMessage::text(json)).await.map_err(ws_error)?;
+            }
+        }
+    }
+
+    /// Accept WebSocket connections and serve each on its own task
+    ///
+    /// Accept errors and the errors that end connections are reported to the handler.
+    pub async fn serve_websockets(&self, listener: TcpListener, devices: Devices) -> io::Result<()> {
+        loop {
+            let socket = match listener.accept().await {
+                Ok((socket, _)) => socket,
+                Err(err) => {
+                    self.accept_failed(err).await;
+                    continue;
+                }
+            };
+            let server = self.clone();
+            let devices = devices.clone();
+            tokio::spawn(async move {
+                if let Err(err) = server.serve_websocket(socket, &devices).await {
+                    server.report(&err);
+                }
+            });
+        }
+    }
+
+    /// Bind to `addr` and serve incoming WebSocket connections
+    pub async fn listen_websocket<A: ToSocketAddrs>(&self, addr: A, devices: Devices) -> io::Result<()> {
+        self.serve_websockets(TcpListener::bind(addr).await?, devices).await
+    }
+
+    /// Handle a control message, returning the reply
+    fn control(&self, control: Control, devices: &Devices) -> Control {
+        match control {
+            Control::ListConnectedDevices { sequence, .. } => {
+                Control::ListConnectedDevices { sequence, devices: Some(devices.get()) }
+            }
+            Control::DevicePixels { sequence, device, pixels } => {
+                let channel = device.and_then(|device| {
+                    devices.get().into_iter().find(|known| known.is_same(&device)).and_then(|known| known.channel)
+                });
+                if let Some(channel) = channel {
+                    self.dispatch(&Message {
+                        channel,
+                        command: crate::Command::SetPixelColors { pixels: Pixels::from_bytes(pixels.into()) },
+                    });
+                }
+                Control::DevicePixels { sequence, device: None, pixels: Vec::new() }
+            }
+            // Only servers send this, so echo it back unchanged
+            other => other,
+        }
+    }
+}
+
+fn ws_error(err: tungstenite::Error) -> OpcError {
+    match err {
+        tungstenite::Error::Io(err) => OpcError::Io(err),
+        err => OpcError::Io(io::Error::other(err)),
+    }
+}
+
+#[test]
+fn should_serialize_fcserver_control_messages() {
+
+    let request: Control = serde_json::from_str(r#"{"type": "list_connected_devices", "sequence": 3}"#).unwrap();
+    assert_eq!(request, Control::ListConnectedDevices { sequence: Some(3), devices: None });
+
+    let mut device = Device::new("fadecandy", "ABC");
+    device.properties.insert("version".to_string(), "1.07".into());
+    let changed = Control::ConnectedDevicesChanged { devices: vec![device.channel(2)] };
+    assert_eq!(serde_json::to_string(&changed).unwrap(),
+               r#"{"type":"connected_devices_changed","devices":[{"type":"fadecandy","serial":"ABC","version":"1.07"}]}"#);
+
+}
+
+#[tokio::test]
+async fn should_serve_websocket_clients() {
+
+    struct Record(tokio::sync::mpsc::UnboundedSender<Message>);
+
+    impl Handler for Record {
+        fn on_pixels(&mut self, channel: u8, pixels: &[[u8; 3]]) {
+            self.0.send(Message::from_pixels(channel, pixels)).unwrap();
+        }
+    }
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let devices = Devices::new();
+    devices.set(vec![Device::new("fadecandy", "ABC").channel(5)]);
+    let server = Server::new(Record(tx));
+    tokio::spawn({
+        let devices = devices.clone();
+        async move { server.serve_websockets(listener, devices).await }
+    });
+
+    let mut client = Client::connect(&format!("ws://{}", addr)).await.unwrap();
+
+    client.set_pixels(1, &[[1, 2, 3]]).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), Message::from_pixels(1, &[[1, 2, 3]]));
+
+    // Fadecandy's browser examples leave the length field zero
+    client.ws.send(WsMessage::binary(vec![3, 0, 0, 0, 7, 8, 9, 10, 11, 12])).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), Message::from_pixels(3, &[[7, 8, 9], [10, 11, 12]]));
+
+    let listed = client.list_connected_devices().await.unwrap();
+    assert_eq!(listed, vec![Device::new("fadecandy", "ABC")]);
+
+    client.device_pixels(&listed[0], &[[4, 5, 6]]).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), Message::from_pixels(5, &[[4, 5, 6]]));
+
+    devices.set(Vec::new());
+    loop {
+        match client.next_control().await.unwrap().unwrap() {
+            Control::ConnectedDevicesChanged { devices } => {
+                assert!(devices.is_empty());
+                break;
+            }
+            Control::DevicePixels { sequence, .. } => assert_eq!(sequence, Some(2)),
+            other => panic!("unexpected control message: {:?}", other),
+        }
+    }
+
+}
+
+#[tokio::test]
+async fn should_report_malformed_websocket_messages() {
+
+    struct Record(tokio::sync::mpsc::UnboundedSender<String>);
+
+    impl Handler for Record {
+        fn on_pixels(&mut self, _channel: u8, _pixels: &[[u8; 3]]) {}
+
+        fn on_error(&mut self, err: &OpcError) {
+            self.0.send(format!("{:?}", err)).unwrap();
+        }
+    }
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(Record(tx));
+    tokio::spawn(async move { server.serve_websockets(listener, Devices::new()).await });
+
+    let mut client = Client::connect(&format!("ws://{}", addr)).await.unwrap();
+
+    client.ws.send(WsMessage::binary(vec![0, 0])).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), "Truncated");
+
+    client.ws.send(WsMessage::binary(vec![0; HEADER_LEN + 65536])).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), "MessageTooLarge(65536)");
+
+}